@@ -0,0 +1,64 @@
+#![no_main]
+
+use achat::{chat, Session};
+use achat_fuzz::{block_on, bounded_lines, exchange, overlong};
+use libfuzzer_sys::fuzz_target;
+use tokio::sync::broadcast;
+
+fuzz_target!(|input: &[u8]| {
+    block_on(async {
+        // Every line is broadcast, up to a quit message or the first line that is not valid UTF-8 or too long.
+        let session = Session::default();
+        let lines = bounded_lines(input);
+        let quit = lines.iter().position(|line| session.is_quit(line));
+        let expected = lines[..quit.unwrap_or(lines.len())]
+            .iter()
+            .map(|line| format!("fuzz: {line}"))
+            .collect::<Vec<_>>();
+        let complete = quit.is_some() || lines.concat().len() == input.len();
+
+        let (tx, mut rx) = broadcast::channel(input.len() + 1);
+        let (result, output) = exchange(input, |reader, writer| {
+            chat::handle_connection(
+                "fuzz",
+                reader,
+                writer,
+                tx.clone(),
+                tx.subscribe(),
+                session.clone(),
+            )
+        })
+        .await;
+
+        let broadcast = std::iter::from_fn(|| rx.try_recv().ok())
+            .map(|(message, peer)| {
+                assert_eq!(peer, "fuzz");
+                message
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(broadcast, expected);
+        // Nobody else is talking, and a client never receives its own messages.
+        assert!(output.is_empty());
+        assert_eq!(result.is_ok(), complete);
+
+        // A line without an end is cut off once it exceeds the limit, and the client dropped.
+        let Some(input) = overlong(input) else {
+            return;
+        };
+        let (result, output) = exchange(&input, |reader, writer| {
+            chat::handle_connection(
+                "fuzz",
+                reader,
+                writer,
+                tx.clone(),
+                tx.subscribe(),
+                session.clone(),
+            )
+        })
+        .await;
+        let error = format!("{:#}", result.expect_err("Overlong line accepted"));
+        assert!(error.contains("Line longer than"), "{error}");
+        assert!(output.is_empty());
+        assert!(rx.try_recv().is_err());
+    });
+});
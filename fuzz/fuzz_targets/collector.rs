@@ -0,0 +1,80 @@
+#![no_main]
+
+use achat::collector::{self, Query};
+use achat_fuzz::{block_on, bounded_lines, exchange, overlong};
+use libfuzzer_sys::fuzz_target;
+use std::collections::HashMap;
+use tokio::sync::mpsc;
+
+fuzz_target!(|input: &[u8]| {
+    block_on(async {
+        let lines = bounded_lines(input);
+        let complete = lines.concat().len() == input.len();
+
+        let (tx, rx) = mpsc::channel(16);
+        let collector = tokio::spawn(collector::collect(rx));
+        let (result, output) = exchange(input, |reader, writer| {
+            collector::handle_connection("fuzz", reader, writer, tx.clone())
+        })
+        .await;
+
+        // A line without an end is cut off once it exceeds the limit, and the client dropped.
+        if let Some(input) = overlong(input) {
+            let (result, output) = exchange(&input, |reader, writer| {
+                collector::handle_connection("fuzz", reader, writer, tx.clone())
+            })
+            .await;
+            let error = format!("{:#}", result.expect_err("Overlong line accepted"));
+            assert!(error.contains("Line longer than"), "{error}");
+            assert!(output.is_empty());
+        }
+        drop(tx);
+        collector.await.unwrap().unwrap();
+        assert_eq!(result.is_ok(), complete);
+
+        // Queries, snapshot, retention, stats, search, follow and hello requests are only checked for not panicking or hanging,
+        // reports must answer plain `report` requests.
+        let is_query = |line: &str| {
+            line.starts_with("hello ")
+                || line.starts_with("search")
+                || line.starts_with("follow")
+                || line.starts_with("unfollow")
+                || matches!(
+                line,
+                "snapshot"
+                    | "snapshot\n"
+                    | "snapshot\r\n"
+                    | "retention"
+                    | "retention\n"
+                    | "retention\r\n"
+                    | "stats"
+                    | "stats\n"
+                    | "stats\r\n"
+            ) || collector::report_query(line)
+                .is_some_and(|query| query.map_or(true, |query| query != Query::default()))
+        };
+        if lines.iter().any(|line| is_query(line)) {
+            return;
+        }
+
+        // One report for each request, each a JSON map holding the text lines received before it.
+        let reports = serde_json::Deserializer::from_slice(&output)
+            .into_iter::<HashMap<String, Vec<String>>>()
+            .collect::<Result<Vec<_>, _>>()
+            .expect("Malformed report");
+        let mut expected = Vec::new();
+        let mut texts = Vec::new();
+        for line in lines {
+            if collector::report_query(line).is_some() {
+                let mut report = HashMap::new();
+                if !texts.is_empty() {
+                    report.insert("fuzz".to_string(), texts.clone());
+                }
+                expected.push(report);
+            } else {
+                texts.push(line.to_string());
+            }
+        }
+        assert_eq!(reports, expected);
+    });
+});
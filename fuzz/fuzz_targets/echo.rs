@@ -0,0 +1,25 @@
+#![no_main]
+
+use achat::echo;
+use achat_fuzz::{block_on, exchange, lines};
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|input: &[u8]| {
+    block_on(async {
+        let (result, output) = exchange(input, |reader, writer| {
+            echo::handle_connection(reader, writer)
+        })
+        .await;
+        assert!(result.is_ok());
+        assert_eq!(output, input);
+
+        // Echoes line by line, up to the first line that is not valid UTF-8.
+        let expected = lines(input).concat();
+        let (result, output) = exchange(input, |reader, writer| {
+            echo::handle_connection_manually(reader, writer)
+        })
+        .await;
+        assert_eq!(output, expected.as_bytes());
+        assert_eq!(result.is_ok(), expected.len() == input.len());
+    });
+});
@@ -0,0 +1,85 @@
+//! Shared harness for the fuzz targets: run a connection handler against arbitrary client bytes
+//! over an in-memory pipe and return what it wrote back.
+
+use std::{future::Future, time::Duration};
+use tokio::io::{AsyncReadExt, AsyncWriteExt, DuplexStream, ReadHalf, WriteHalf};
+
+/// Size of the in-memory pipe buffer. Small, so that lines arrive split over several reads.
+const PIPE_CAPACITY: usize = 64;
+
+/// How long a handler may take for a single input before it is considered hung.
+const TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Run `future` to completion on a fresh single-threaded runtime.
+pub fn block_on<F: Future>(future: F) -> F::Output {
+    tokio::runtime::Builder::new_current_thread()
+        .enable_time()
+        .build()
+        .unwrap()
+        .block_on(future)
+}
+
+/// Feed `input` to `handler` through an in-memory pipe, then close the client's sending side.
+/// Returns the handler's result and everything it wrote until it finished.
+///
+/// # Panics
+/// Panics if the handler does not finish within [`TIMEOUT`].
+pub async fn exchange<H, Fut, T>(input: &[u8], handler: H) -> (T, Vec<u8>)
+where
+    H: FnOnce(ReadHalf<DuplexStream>, WriteHalf<DuplexStream>) -> Fut,
+    Fut: Future<Output = T>,
+{
+    let (client, server) = tokio::io::duplex(PIPE_CAPACITY);
+    let (server_reader, server_writer) = tokio::io::split(server);
+    let (mut client_reader, mut client_writer) = tokio::io::split(client);
+
+    let send = async move {
+        // The handler may stop reading at any point, so failing to send is fine.
+        if client_writer.write_all(input).await.is_ok() {
+            let _ = client_writer.shutdown().await;
+        }
+    };
+    let receive = async move {
+        let mut output = Vec::new();
+        client_reader.read_to_end(&mut output).await.unwrap();
+        output
+    };
+    let handle = async move { handler(server_reader, server_writer).await };
+
+    let (result, (), output) =
+        tokio::time::timeout(TIMEOUT, async { tokio::join!(handle, send, receive) })
+            .await
+            .expect("Handler hung");
+    (result, output)
+}
+
+/// Split `input` into lines the way [`tokio::io::AsyncBufReadExt::read_line`] does,
+/// stopping at the first line that is not valid UTF-8.
+pub fn lines(input: &[u8]) -> Vec<&str> {
+    input
+        .split_inclusive(|&b| b == b'\n')
+        .map(std::str::from_utf8)
+        .map_while(Result::ok)
+        .collect()
+}
+
+/// Like [`lines`], but also stopping at the first line longer than [`achat::MAX_LINE`],
+/// which the chat servers and the collector reject.
+pub fn bounded_lines(input: &[u8]) -> Vec<&str> {
+    let mut lines = lines(input);
+    if let Some(overlong) = lines.iter().position(|line| {
+        line.len() > achat::MAX_LINE || line.len() == achat::MAX_LINE && !line.ends_with('\n')
+    }) {
+        lines.truncate(overlong);
+    }
+    lines
+}
+
+/// Repeat newline-free `input` until it is longer than [`achat::MAX_LINE`].
+/// Returns `None` if `input` is empty or holds a newline.
+pub fn overlong(input: &[u8]) -> Option<Vec<u8>> {
+    if input.is_empty() || input.contains(&b'\n') {
+        return None;
+    }
+    Some(input.repeat(achat::MAX_LINE / input.len() + 1))
+}
@@ -1,16 +1,105 @@
-use std::net::SocketAddr;
+use std::{net::SocketAddr, path::PathBuf};
 
+use anyhow::Context;
 use clap::Parser;
 
+use crate::{Config, LogFormat};
+
 /// Command Line Arguments.
+///
+/// Each argument may also be given as an environment variable.
+/// Arguments override values from the `[server]` section of the configuration file,
+/// the other sections can only be set in the file.
 #[derive(Parser, Debug)]
 #[clap(author, version, about, long_about = None)]
 pub struct Arguments {
-    /// Address to listen on.
-    #[clap(short, long, value_parser, default_value = "127.0.0.1:8080")]
-    pub address: SocketAddr,
+    /// Path to a TOML configuration file.
+    #[clap(long, value_parser, env = "ACHAT_CONFIG")]
+    pub config: Option<PathBuf>,
+
+    /// Address to listen on [default: 127.0.0.1:8080].
+    #[clap(short, long, value_parser, env = "ACHAT_ADDRESS")]
+    pub address: Option<SocketAddr>,
 
     /// Address to publish console events on.
-    #[clap(short, long, value_parser)]
+    #[clap(short, long, value_parser, env = "ACHAT_CONSOLE")]
     pub console: Option<SocketAddr>,
+
+    /// Log filter, for example `info` or `achat=debug,warn` (defaults to `RUST_LOG`, then `info`).
+    #[clap(short, long, value_parser, env = "ACHAT_LOG")]
+    pub log: Option<String>,
+
+    /// Format of log output [default: human].
+    #[clap(long, value_enum, env = "ACHAT_LOG_FORMAT")]
+    pub log_format: Option<LogFormat>,
+
+    /// Capacity of the channels between clients and server [default: 16].
+    #[clap(long, value_parser, env = "ACHAT_CAPACITY")]
+    pub capacity: Option<usize>,
+
+    /// Maximum number of clients served at the same time [default: unlimited].
+    #[clap(long, value_parser, env = "ACHAT_MAX_CONNECTIONS")]
+    pub max_connections: Option<usize>,
+
+    /// Seconds connections get to finish after shutdown, before they are aborted [default: 5].
+    #[clap(long, value_parser, env = "ACHAT_GRACE_PERIOD")]
+    pub grace_period: Option<u64>,
+
+    /// File with the message of the day, sent to clients of the chat servers on connect.
+    #[clap(long, value_parser, env = "ACHAT_MOTD")]
+    pub motd: Option<PathBuf>,
+
+    /// Line which disconnects a client of the chat servers [default: quit].
+    #[clap(long, value_parser, env = "ACHAT_QUIT")]
+    pub quit: Option<String>,
+
+    /// Seconds after which an idle client of the chat servers is disconnected [default: never].
+    #[clap(long, value_parser, env = "ACHAT_IDLE_TIMEOUT")]
+    pub idle_timeout: Option<u64>,
+}
+
+impl Arguments {
+    /// Load the configuration file (if any) and apply the arguments on top of it.
+    ///
+    /// # Errors
+    /// Returns an error if the configuration file can not be loaded or the result is invalid.
+    pub fn config(&self) -> anyhow::Result<Config> {
+        let mut config = match &self.config {
+            Some(path) => Config::from_file(path)?,
+            None => Config::default(),
+        };
+        let server = &mut config.server;
+        if let Some(address) = self.address {
+            server.address = address;
+        }
+        if let Some(console) = self.console {
+            server.console = Some(console);
+        }
+        if let Some(log) = &self.log {
+            server.log = Some(log.clone());
+        }
+        if let Some(log_format) = self.log_format {
+            server.log_format = log_format;
+        }
+        if let Some(capacity) = self.capacity {
+            server.capacity = capacity;
+        }
+        if let Some(max_connections) = self.max_connections {
+            server.max_connections = Some(max_connections);
+        }
+        if let Some(grace_period) = self.grace_period {
+            server.grace_period_secs = grace_period;
+        }
+        if let Some(motd) = &self.motd {
+            server.motd = Some(motd.clone());
+        }
+        if let Some(quit) = &self.quit {
+            server.quit = quit.clone();
+        }
+        if let Some(idle_timeout) = self.idle_timeout {
+            server.idle_timeout_secs = Some(idle_timeout);
+        }
+        config.validate().context("Invalid configuration")?;
+        Ok(config)
+    }
 }
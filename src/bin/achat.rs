@@ -0,0 +1,50 @@
+use achat::{client, init_logging, serve, Arguments};
+use clap::{Parser, Subcommand};
+
+/// Run any of the achat servers, or connect to one.
+#[derive(Parser, Debug)]
+#[clap(author, version, about, long_about = None)]
+struct Cli {
+    #[clap(flatten)]
+    args: Arguments,
+
+    #[clap(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Run a server on the configured address.
+    Serve {
+        /// Which server to run.
+        #[clap(value_enum)]
+        server: serve::Kind,
+    },
+
+    /// Connect `stdin` and `stdout` to the server at the configured address.
+    Connect,
+}
+
+#[tokio::main]
+async fn main() -> anyhow::Result<()> {
+    let cli = Cli::parse();
+    let config = cli.args.config()?;
+
+    match cli.command {
+        Command::Serve { server } => {
+            let settings = &config.server;
+            init_logging(
+                settings.log.as_deref(),
+                settings.log_format,
+                settings.console,
+            )?;
+
+            serve::start(server, &config)
+                .await?
+                .shutdown_on_ctrl_c()
+                .join()
+                .await
+        }
+        Command::Connect => client::connect(config.server.address).await,
+    }
+}
@@ -1,33 +1,16 @@
-use achat::{chat, init_console_subscriber, Arguments};
-use anyhow::Context;
+use achat::{init_logging, serve, Arguments};
 use clap::Parser;
-use tokio::{net::TcpListener, sync::broadcast};
 
 #[tokio::main]
 async fn main() -> anyhow::Result<()> {
-    let args = Arguments::parse();
+    let config = Arguments::parse().config()?;
+    let server = &config.server;
 
-    if let Some(addr) = args.console {
-        init_console_subscriber(addr);
-    }
+    init_logging(server.log.as_deref(), server.log_format, server.console)?;
 
-    let listener = TcpListener::bind(&args.address)
+    serve::chat(&config)
+        .await?
+        .shutdown_on_ctrl_c()
+        .join()
         .await
-        .context(format!("Failed to bind on {}", &args.address))?;
-
-    let (tx, _rx) = broadcast::channel(16);
-
-    loop {
-        if let Ok((mut socket, addr)) = listener.accept().await {
-            println!("Received connection from {addr}");
-
-            let tx = tx.clone();
-            let rx = tx.subscribe();
-
-            tokio::spawn(async move {
-                let (reader, writer) = socket.split();
-                chat::handle_connection(addr, reader, writer, tx, rx).await
-            });
-        }
-    }
 }
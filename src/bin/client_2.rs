@@ -7,9 +7,9 @@ use tokio::{
 
 #[tokio::main]
 async fn main() -> anyhow::Result<()> {
-    let args = Arguments::parse();
+    let config = Arguments::parse().config()?;
 
-    let mut stream = TcpStream::connect(args.address).await?;
+    let mut stream = TcpStream::connect(config.server.address).await?;
     let (mut reader, mut writer) = stream.split();
     let (mut stdin, mut stdout) = (stdin(), stdout());
     tokio::try_join!(
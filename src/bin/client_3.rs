@@ -6,9 +6,9 @@ use tokio::net::TcpStream;
 
 #[tokio::main]
 async fn main() -> anyhow::Result<()> {
-    let args = Arguments::parse();
+    let config = Arguments::parse().config()?;
 
-    let mut stream = TcpStream::connect(args.address).await?;
+    let mut stream = TcpStream::connect(config.server.address).await?;
     let mut rw = ReadWriteTokio::new(stdin(), stdout());
     tokio::try_join!(copy_bidirectional(&mut rw, &mut stream))?;
     Ok(())
@@ -1,37 +1,16 @@
-use achat::{collector, init_console_subscriber, Arguments};
-use anyhow::Context;
+use achat::{init_logging, serve, Arguments};
 use clap::Parser;
-use tokio::{net::TcpListener, sync::mpsc};
 
 #[tokio::main]
 async fn main() -> anyhow::Result<()> {
-    let args = Arguments::parse();
+    let config = Arguments::parse().config()?;
+    let server = &config.server;
 
-    if let Some(addr) = args.console {
-        init_console_subscriber(addr);
-    }
+    init_logging(server.log.as_deref(), server.log_format, server.console)?;
 
-    let listener = TcpListener::bind(&args.address)
+    serve::collector(&config)
+        .await?
+        .shutdown_on_ctrl_c()
+        .join()
         .await
-        .context(format!("Failed to bind on {}", &args.address))?;
-
-    let (tx, rx) = mpsc::channel(16);
-
-    let _handle = tokio::spawn(collector::collect(rx));
-
-    loop {
-        let (mut socket, addr) = listener
-            .accept()
-            .await
-            .context("Failed to accept on socket")?;
-
-        let tx = tx.clone();
-
-        tokio::spawn(async move {
-            let (reader, writer) = socket.split();
-            collector::handle_connection(addr.to_string(), reader, writer, tx)
-                .await
-                .expect("Failed to handle connection");
-        });
-    }
 }
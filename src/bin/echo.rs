@@ -1,31 +1,16 @@
-use achat::{echo, init_console_subscriber, Arguments};
-use anyhow::Context;
+use achat::{init_logging, serve, Arguments};
 use clap::Parser;
-use tokio::net::TcpListener;
 
 #[tokio::main]
 async fn main() -> anyhow::Result<()> {
-    let args = Arguments::parse();
+    let config = Arguments::parse().config()?;
+    let server = &config.server;
 
-    if let Some(addr) = args.console {
-        init_console_subscriber(addr);
-    }
+    init_logging(server.log.as_deref(), server.log_format, server.console)?;
 
-    let listener = TcpListener::bind(&args.address)
+    serve::echo(&config)
+        .await?
+        .shutdown_on_ctrl_c()
+        .join()
         .await
-        .with_context(|| format!("Failed to bind on {}", &args.address))?;
-
-    loop {
-        let (mut socket, _addr) = listener
-            .accept()
-            .await
-            .context("Failed to accept on socket")?;
-
-        tokio::spawn(async move {
-            let (reader, writer) = socket.split();
-            echo::handle_connection(reader, writer)
-                .await
-                .expect("Failed to handle connection");
-        });
-    }
 }
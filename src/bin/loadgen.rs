@@ -0,0 +1,288 @@
+use anyhow::Context;
+use clap::Parser;
+use serde::Serialize;
+use std::{net::SocketAddr, time::Duration};
+use tokio::{
+    io::{AsyncBufReadExt, AsyncWriteExt, BufReader},
+    net::TcpStream,
+    task::JoinSet,
+    time::{Instant, MissedTickBehavior},
+};
+
+/// Open many clients against an achat server, send messages at a fixed rate,
+/// and measure throughput and end-to-end latency of the messages coming back.
+#[derive(Parser, Debug)]
+#[clap(author, version, about, long_about = None)]
+struct Arguments {
+    /// Address of the server.
+    #[clap(short, long, value_parser, default_value = "127.0.0.1:8080")]
+    address: SocketAddr,
+
+    /// Number of concurrent clients.
+    #[clap(short = 'n', long, value_parser, default_value_t = 10)]
+    clients: usize,
+
+    /// Messages per second sent by each client.
+    #[clap(short, long, value_parser, default_value_t = 10.0)]
+    rate: f64,
+
+    /// Seconds to send messages for.
+    #[clap(short, long, value_parser, default_value_t = 10.0)]
+    duration: f64,
+
+    /// Seconds to wait for messages still in flight after sending stopped.
+    #[clap(long, value_parser, default_value_t = 1.0)]
+    drain: f64,
+
+    /// Size of each message in bytes, including the newline.
+    #[clap(short, long, value_parser, default_value_t = 64)]
+    size: usize,
+
+    /// Print the report as JSON.
+    #[clap(long)]
+    json: bool,
+}
+
+/// Marks lines sent by the load generator: `loadgen <client> <sequence> <nanoseconds since start>`.
+const MARKER: &str = "loadgen ";
+
+/// What a single client observed.
+#[derive(Debug)]
+struct ClientResult {
+    sent: u64,
+    received: u64,
+    latencies: Vec<Duration>,
+}
+
+#[derive(Debug, Serialize)]
+struct Report {
+    clients: usize,
+    duration_secs: f64,
+    sent: u64,
+    received: u64,
+    sent_per_sec: f64,
+    received_per_sec: f64,
+    latency_ms: Option<Latency>,
+}
+
+#[derive(Debug, Serialize)]
+struct Latency {
+    min: f64,
+    p50: f64,
+    p90: f64,
+    p99: f64,
+    max: f64,
+    mean: f64,
+}
+
+impl Latency {
+    /// Summarize `latencies`, or `None` if there are none.
+    fn from_samples(mut latencies: Vec<Duration>) -> Option<Self> {
+        if latencies.is_empty() {
+            return None;
+        }
+        latencies.sort_unstable();
+        let ms = |d: Duration| d.as_secs_f64() * 1000.0;
+        let percentile = |p: f64| {
+            let rank = (p / 100.0 * latencies.len() as f64).ceil() as usize;
+            ms(latencies[rank.clamp(1, latencies.len()) - 1])
+        };
+        let total: Duration = latencies.iter().sum();
+        Some(Self {
+            min: ms(latencies[0]),
+            p50: percentile(50.0),
+            p90: percentile(90.0),
+            p99: percentile(99.0),
+            max: ms(latencies[latencies.len() - 1]),
+            mean: ms(total) / latencies.len() as f64,
+        })
+    }
+}
+
+impl std::fmt::Display for Report {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "clients:    {}", self.clients)?;
+        writeln!(f, "duration:   {:.2}s", self.duration_secs)?;
+        writeln!(
+            f,
+            "sent:       {} ({:.1} msg/s)",
+            self.sent, self.sent_per_sec
+        )?;
+        writeln!(
+            f,
+            "received:   {} ({:.1} msg/s)",
+            self.received, self.received_per_sec
+        )?;
+        match &self.latency_ms {
+            Some(l) => write!(
+                f,
+                "latency ms: min {:.3}, p50 {:.3}, p90 {:.3}, p99 {:.3}, max {:.3}, mean {:.3}",
+                l.min, l.p50, l.p90, l.p99, l.max, l.mean
+            ),
+            None => write!(f, "latency ms: no messages received"),
+        }
+    }
+}
+
+/// Send messages from client `id` at `interval` until `stop`, and measure the latency of everything received
+/// until `drained`.
+async fn run_client(
+    id: usize,
+    stream: TcpStream,
+    start: Instant,
+    interval: Duration,
+    stop: Instant,
+    drained: Instant,
+    size: usize,
+) -> anyhow::Result<ClientResult> {
+    let (reader, mut writer) = stream.into_split();
+
+    let send = async {
+        let mut sent = 0;
+        let mut ticker = tokio::time::interval(interval);
+        ticker.set_missed_tick_behavior(MissedTickBehavior::Delay);
+        while ticker.tick().await < stop {
+            let nanos = start.elapsed().as_nanos();
+            let mut message = format!("{MARKER}{id} {sent} {nanos} ");
+            let padding = size.saturating_sub(message.len() + 1);
+            message.extend(std::iter::repeat_n('x', padding));
+            message.push('\n');
+            writer
+                .write_all(message.as_bytes())
+                .await
+                .context("Failed to send")?;
+            sent += 1;
+        }
+        anyhow::Ok(sent)
+    };
+
+    let receive = async {
+        let mut received = 0;
+        let mut latencies = Vec::new();
+        let mut reader = BufReader::new(reader);
+        let mut line = String::new();
+        loop {
+            match tokio::time::timeout_at(drained, reader.read_line(&mut line)).await {
+                // Timed out or closed by the server.
+                Err(_) | Ok(Ok(0)) => break,
+                Ok(Ok(_)) => {}
+                Ok(Err(e)) => return Err(e).context("Failed to receive"),
+            }
+            let now = start.elapsed();
+            // Servers may prefix the line, for example with the sender's address.
+            if let Some(sent_at) = line
+                .find(MARKER)
+                .and_then(|i| line[i + MARKER.len()..].split_whitespace().nth(2))
+                .and_then(|nanos| nanos.parse::<u64>().ok())
+            {
+                received += 1;
+                latencies.push(now.saturating_sub(Duration::from_nanos(sent_at)));
+            }
+            line.clear();
+        }
+        anyhow::Ok((received, latencies))
+    };
+
+    let (sent, (received, latencies)) = tokio::try_join!(send, receive)?;
+    Ok(ClientResult {
+        sent,
+        received,
+        latencies,
+    })
+}
+
+/// The duration of `secs` seconds given as the argument `name`.
+///
+/// # Errors
+/// Returns an error if `secs` is negative, not finite or too large.
+fn seconds(name: &str, secs: f64) -> anyhow::Result<Duration> {
+    Duration::try_from_secs_f64(secs).with_context(|| {
+        format!("Invalid {name} {secs:?}, expected a finite number of seconds >= 0")
+    })
+}
+
+/// When to stop sending and when to stop receiving, for a run of `duration` starting at `start`, drained for `drain`.
+///
+/// # Errors
+/// Returns an error if either is too far in the future to represent.
+fn deadlines(
+    start: Instant,
+    duration: Duration,
+    drain: Duration,
+) -> anyhow::Result<(Instant, Instant)> {
+    let stop = start
+        .checked_add(duration)
+        .with_context(|| format!("Duration {duration:?} is too long"))?;
+    let drained = stop
+        .checked_add(drain)
+        .with_context(|| format!("Drain {drain:?} is too long"))?;
+    Ok((stop, drained))
+}
+
+#[tokio::main]
+async fn main() -> anyhow::Result<()> {
+    let args = Arguments::parse();
+    anyhow::ensure!(args.clients > 0, "Need at least one client");
+    anyhow::ensure!(
+        args.rate.is_finite() && args.rate > 0.0,
+        "Rate must be a finite number greater than zero"
+    );
+    let interval = seconds("interval", 1.0 / args.rate)?;
+    anyhow::ensure!(
+        !interval.is_zero(),
+        "Rate {:?} is too high, the interval between messages would be zero",
+        args.rate
+    );
+    let duration = seconds("duration", args.duration)?;
+    let drain = seconds("drain", args.drain)?;
+    // Fail before connecting, the deadlines are computed again once connected.
+    deadlines(Instant::now(), duration, drain)?;
+
+    let mut streams = Vec::with_capacity(args.clients);
+    for _ in 0..args.clients {
+        streams.push(
+            TcpStream::connect(args.address)
+                .await
+                .with_context(|| format!("Failed to connect to {}", args.address))?,
+        );
+    }
+
+    let start = Instant::now();
+    let (stop, drained) = deadlines(start, duration, drain)?;
+
+    let mut clients = JoinSet::new();
+    for (id, stream) in streams.into_iter().enumerate() {
+        clients.spawn(run_client(
+            id, stream, start, interval, stop, drained, args.size,
+        ));
+    }
+
+    let mut sent = 0;
+    let mut received = 0;
+    let mut latencies = Vec::new();
+    while let Some(result) = clients.join_next().await {
+        let result = result.context("Client task failed")??;
+        sent += result.sent;
+        received += result.received;
+        latencies.extend(result.latencies);
+    }
+
+    let secs = duration.as_secs_f64();
+    // Nothing is sent in no time.
+    let per_sec = |count: u64| if secs > 0.0 { count as f64 / secs } else { 0.0 };
+    let report = Report {
+        clients: args.clients,
+        duration_secs: secs,
+        sent,
+        received,
+        sent_per_sec: per_sec(sent),
+        received_per_sec: per_sec(received),
+        latency_ms: Latency::from_samples(latencies),
+    };
+    if args.json {
+        println!("{}", serde_json::to_string_pretty(&report)?);
+    } else {
+        println!("{report}");
+    }
+    Ok(())
+}
@@ -1,52 +1,75 @@
 use anyhow::Context;
-use std::net::SocketAddr;
+use std::fmt::{Debug, Display};
 use tokio::{
-    io::{AsyncBufReadExt, AsyncRead, AsyncWrite, AsyncWriteExt, BufReader},
+    io::{AsyncRead, AsyncWrite, AsyncWriteExt, BufReader},
     sync::broadcast,
 };
 
-use crate::is_quit;
+use crate::{read_bounded_line, sleep_until, Session};
 
 /// Monitor the `reader` and the `rx` for messages.
 /// When receiving bytes on `reader`, forward them on the [`broadcast::Sender`].
-/// When receiving a message on `rx`, where the source socket address is not our own,
+/// When receiving a message on `rx`, where the source `peer` is not our own,
 /// forward it on `writer` (else, discard it).
 ///
 /// # Termination
 /// If an error or `None` is encountered, the future terminates.
 /// If EOF is signalled on `reader` by `Ok(0)`, the future terminates.
-/// If the text read from `reader` is `"quit"` or `"quit\n"` or `"quit\r\n"`, the future terminates.
-pub async fn handle_connection<Reader, Writer>(
-    addr: SocketAddr,
+/// If a line read from `reader` exceeds [`MAX_LINE`](crate::MAX_LINE) bytes, the future fails.
+/// If the line read from `reader` is the quit phrase of the `session` (see [`Session::is_quit`]), the future terminates.
+/// If no line is read from `reader` within the idle timeout of the `session`, the future terminates.
+#[tracing::instrument(name = "connection", skip_all, fields(peer = %peer))]
+pub async fn handle_connection<Reader, Writer, Peer>(
+    peer: Peer,
     reader: Reader,
     mut writer: Writer,
-    tx: broadcast::Sender<(String, SocketAddr)>,
-    mut rx: broadcast::Receiver<(String, SocketAddr)>,
+    tx: broadcast::Sender<(String, Peer)>,
+    mut rx: broadcast::Receiver<(String, Peer)>,
+    session: Session,
 ) -> anyhow::Result<()>
 where
     Reader: AsyncRead + Unpin,
     Writer: AsyncWrite + Unpin,
+    Peer: Display + Debug + PartialEq + Clone + Send + Sync + 'static,
 {
-    let mut line = String::new();
+    // Unlike `read_line`, `read_bounded_line` is cancel safe: bytes read before another branch completes stay in `line`.
+    let mut line = Vec::new();
     let mut reader = BufReader::new(reader);
+    let mut idle = session.idle_deadline();
 
     loop {
         tokio::select! {
-            Ok(bytes_read) = reader.read_line(&mut line) => {
-                if bytes_read == 0 {
+            result = read_bounded_line(&mut reader, &mut line) => {
+                if let Err(e) = result {
+                    break Err(e).context("Failed to read line");
+                }
+                if line.is_empty() {
+                    tracing::info!("Client disconnected");
                     break Ok::<(), anyhow::Error>(()); // EOF detected.
                 }
-                if is_quit(&line) {
+                idle = session.idle_deadline();
+                let Ok(text) = std::str::from_utf8(&line) else {
+                    break Err(anyhow::anyhow!("Received invalid UTF-8"));
+                };
+                if session.is_quit(text) {
+                    tracing::info!("Client quit");
                     break Ok(());
                 }
-                tx.send((format!("{addr}: {line}"), addr)).context("Failed to broadcast message")?;
+                tracing::debug!(bytes = line.len(), "Broadcasting message");
+                tx.send((format!("{peer}: {text}"), peer.clone())).context("Failed to broadcast message")?;
+                line.clear();
             },
             Ok((message, source)) = rx.recv() => {
-                if source == addr {
+                if source == peer {
                     continue;
                 }
                 writer.write_all(message.as_bytes()).await.context("Failed to forward message")?;
             }
+            () = sleep_until(idle) => {
+                tracing::info!("Client idle for too long");
+                writer.write_all(b"Disconnected after being idle\n").await.context("Failed to send idle notice")?;
+                break Ok(());
+            }
             else => {
                 break Ok(());
             }
@@ -63,6 +86,8 @@ where
 #[cfg(test)]
 mod test {
     use super::*;
+    use crate::transport::PeerId;
+    use std::net::SocketAddr;
     use std::time::Duration;
     use tokio_test::io::Builder as Mock;
 
@@ -74,11 +99,12 @@ mod test {
         let (tx, mut rx) = broadcast::channel(16);
 
         let handle = tokio::spawn(handle_connection(
-            "127.0.0.3:8081".parse().unwrap(),
+            "127.0.0.3:8081".parse::<SocketAddr>().unwrap(),
             reader,
             writer,
             tx.clone(),
             tx.subscribe(),
+            Session::default(),
         ));
 
         let (message, socket) = rx.recv().await.unwrap();
@@ -93,6 +119,102 @@ mod test {
         tokio::join!(handle).0.unwrap().unwrap();
     }
 
+    #[tokio::test]
+    async fn broadcasts_message_from_any_peer() {
+        let writer = Mock::new().build();
+        let reader = Mock::new().read(b"hello").build();
+
+        let (tx, mut rx) = broadcast::channel(16);
+
+        let handle = tokio::spawn(handle_connection(
+            PeerId::Memory(3),
+            reader,
+            writer,
+            tx.clone(),
+            tx.subscribe(),
+            Session::default(),
+        ));
+
+        let (message, peer) = rx.recv().await.unwrap();
+        assert_eq!(
+            (message, peer),
+            ("memory:3: hello".to_string(), PeerId::Memory(3))
+        );
+
+        tokio::join!(handle).0.unwrap().unwrap();
+    }
+
+    #[tokio::test]
+    async fn fails_on_invalid_utf8() {
+        let writer = Mock::new().build();
+        let reader = Mock::new().read(b"\xff\n").build();
+
+        let (tx, _rx) = broadcast::channel(16);
+
+        let result = handle_connection(
+            PeerId::Memory(3),
+            reader,
+            writer,
+            tx.clone(),
+            tx.subscribe(),
+            Session::default(),
+        )
+        .await;
+        assert!(result.is_err());
+    }
+
+    #[tokio::test]
+    async fn drops_client_sending_overlong_line() {
+        let writer = Mock::new().build();
+        let reader = Mock::new().read(&[b'x'; crate::MAX_LINE]).build();
+
+        let (tx, mut rx) = broadcast::channel(16);
+
+        let result = handle_connection(
+            PeerId::Memory(3),
+            reader,
+            writer,
+            tx.clone(),
+            tx.subscribe(),
+            Session::default(),
+        )
+        .await;
+        let error = format!("{:#}", result.unwrap_err());
+        assert!(error.contains("Line longer than 65536 bytes"), "{error}");
+        assert!(rx.try_recv().is_err());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn keeps_partial_line_while_forwarding() {
+        let writer = Mock::new().write(b"memory:1: hi\n").build();
+        let reader = Mock::new()
+            .read(b"hel")
+            .wait(Duration::from_secs(1))
+            .read(b"lo\n")
+            .build();
+
+        let (tx, mut rx) = broadcast::channel(16);
+
+        let handle = tokio::spawn(handle_connection(
+            PeerId::Memory(3),
+            reader,
+            writer,
+            tx.clone(),
+            tx.subscribe(),
+            Session::default(),
+        ));
+
+        tokio::time::sleep(Duration::from_millis(500)).await;
+        tx.send(("memory:1: hi\n".to_string(), PeerId::Memory(1)))
+            .unwrap();
+
+        rx.recv().await.unwrap();
+        let (message, _) = rx.recv().await.unwrap();
+        assert_eq!(message, "memory:3: hello\n");
+
+        tokio::join!(handle).0.unwrap().unwrap();
+    }
+
     #[tokio::test(start_paused = true)]
     async fn receives_message() {
         let writer = Mock::new().write(b"how's it going").build();
@@ -102,11 +224,12 @@ mod test {
         let (tx, _rx) = broadcast::channel(1);
 
         let handle = tokio::spawn(handle_connection(
-            "127.0.0.3:8081".parse().unwrap(),
+            "127.0.0.3:8081".parse::<SocketAddr>().unwrap(),
             reader,
             writer,
             tx.clone(),
             tx.subscribe(),
+            Session::default(),
         ));
 
         tx.send((
@@ -117,4 +240,67 @@ mod test {
 
         tokio::join!(handle).0.unwrap().unwrap();
     }
+
+    #[tokio::test]
+    async fn quits_on_configured_phrase() {
+        let writer = Mock::new().build();
+        let reader = Mock::new().read(b"quit\n").read(b"/bye\r\n").build();
+
+        let (tx, mut rx) = broadcast::channel(16);
+        let session = Session {
+            quit: "/bye".to_string(),
+            idle_timeout: None,
+        };
+
+        handle_connection(
+            PeerId::Memory(3),
+            reader,
+            writer,
+            tx.clone(),
+            tx.subscribe(),
+            session,
+        )
+        .await
+        .unwrap();
+
+        let (message, _) = rx.recv().await.unwrap();
+        assert_eq!(message, "memory:3: quit\n");
+        assert!(rx.try_recv().is_err());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn disconnects_idle_client() {
+        let writer = Mock::new()
+            .write(b"Disconnected after being idle\n")
+            .build();
+        let reader = Mock::new()
+            .read(b"hello\n")
+            .wait(Duration::from_secs(8))
+            .read(b"still here\n")
+            .wait(Duration::from_secs(60))
+            .build();
+
+        let (tx, mut rx) = broadcast::channel(16);
+        let session = Session {
+            quit: "quit".to_string(),
+            idle_timeout: Some(Duration::from_secs(10)),
+        };
+
+        let start = tokio::time::Instant::now();
+        handle_connection(
+            PeerId::Memory(3),
+            reader,
+            writer,
+            tx.clone(),
+            tx.subscribe(),
+            session,
+        )
+        .await
+        .unwrap();
+
+        // The timeout starts over with each line.
+        assert_eq!(start.elapsed(), Duration::from_secs(18));
+        assert_eq!(rx.recv().await.unwrap().0, "memory:3: hello\n");
+        assert_eq!(rx.recv().await.unwrap().0, "memory:3: still here\n");
+    }
 }
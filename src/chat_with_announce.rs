@@ -1,51 +1,133 @@
 use anyhow::Context;
-use std::{net::SocketAddr, time::Duration};
+use chrono::{DateTime, Utc};
+use std::{
+    fmt::{Debug, Display},
+    net::IpAddr,
+    str::FromStr,
+    sync::Arc,
+    time::Duration,
+};
 use tokio::{
-    io::{AsyncBufReadExt, AsyncRead, AsyncWrite, AsyncWriteExt, BufReader},
+    io::{AsyncRead, AsyncWrite, AsyncWriteExt, BufReader},
     sync::{broadcast, watch},
+    time::Instant,
+};
+
+use crate::{
+    read_bounded_line, sleep_until,
+    stats::{Statistics, Stats},
+    transport::PeerId,
+    Session,
 };
 
-/// Monitor the `reader`, `rx` and `topic_rx` for messages.
+/// Monitor the `reader`, `rx`, the `topic` and `announcements` for messages.
+/// On connect, tell the client the current topic.
 /// When receiving bytes on `reader`, forward them on the [`broadcast::Sender`].
-/// When receiving a message on `rx`, where the source socket address is not our own,
+/// When receiving `/topic <text>` on `reader`, change the topic if the client is allowed to (see [`Topic`]).
+/// When receiving `/stats` on `reader`, query `stats` and forward the result on `writer`.
+/// The client's connection and broadcast messages are reported to `stats`.
+/// When receiving a message on `rx`, where the source `peer` is not our own,
 /// forward it on `writer` (else, discard it).
-/// When the content of `topic_rx` changed, fetch it, then format and forward it on `writer`.
+/// When the topic changed, or a new announcement arrives on `announcements`, fetch it, then format and forward it on `writer`
+/// (one line per line of the announcement).
 ///
 /// # Termination
 /// If an error or `None` is encountered, the future terminates.
 /// If EOF is signalled on `reader` by `Ok(0)`, the future terminates.
-pub async fn handle_connection<Reader, Writer>(
-    addr: SocketAddr,
+/// If a line read from `reader` exceeds [`MAX_LINE`](crate::MAX_LINE) bytes, the future fails.
+/// If the line read from `reader` is the quit phrase of the `session` (see [`Session::is_quit`]), the future terminates.
+/// If no line is read from `reader` within the idle timeout of the `session`, the future terminates.
+#[tracing::instrument(name = "connection", skip_all, fields(peer = %peer))]
+#[allow(clippy::too_many_arguments)]
+pub async fn handle_connection<Reader, Writer, Peer>(
+    peer: Peer,
     reader: Reader,
     mut writer: Writer,
-    tx: broadcast::Sender<(String, SocketAddr)>,
-    mut rx: broadcast::Receiver<(String, SocketAddr)>,
-    mut topic_rx: watch::Receiver<String>,
+    tx: broadcast::Sender<(String, Peer)>,
+    mut rx: broadcast::Receiver<(String, Peer)>,
+    topic: Topic,
+    mut announcements: watch::Receiver<String>,
+    stats: Statistics,
+    session: Session,
 ) -> anyhow::Result<()>
 where
     Reader: AsyncRead + Unpin,
     Writer: AsyncWrite + Unpin,
+    Peer: Display + Debug + PartialEq + Clone + Into<PeerId> + Send + Sync + 'static,
 {
-    let mut line = String::new();
+    let connection = stats.connect();
+    let mut topic_rx = topic.subscribe();
+    let current = format!("Topic: {}\n", *topic_rx.borrow_and_update());
+    writer
+        .write_all(current.as_bytes())
+        .await
+        .context("Unable to send topic")?;
+
+    // Unlike `read_line`, `read_bounded_line` is cancel safe: bytes read before another branch completes stay in `line`.
+    let mut line = Vec::new();
     let mut reader = BufReader::new(reader);
+    let mut idle = session.idle_deadline();
 
     loop {
         tokio::select! {
-            Ok(bytes_read) = reader.read_line(&mut line) => {
-                if bytes_read == 0 {
+            result = read_bounded_line(&mut reader, &mut line) => {
+                if let Err(e) = result {
+                    break Err(e).context("Failed to read line");
+                }
+                if line.is_empty() {
+                    tracing::info!("Client disconnected");
                     break Ok(()); // EOF detected.
                 }
-                tx.send((format!("{addr}: {line}"), addr)).context("Failed to broadcast message from client")?;
+                idle = session.idle_deadline();
+                let Ok(text) = std::str::from_utf8(&line) else {
+                    break Err(anyhow::anyhow!("Received invalid UTF-8"));
+                };
+                if session.is_quit(text) {
+                    tracing::info!("Client quit");
+                    break Ok(());
+                }
+                if let Some(text) = topic_command(text) {
+                    if !topic.may_set(&peer.clone().into()) {
+                        writer.write_all(b"Not allowed to change the topic\n").await.context("Unable to reject topic change")?;
+                    } else if text.is_empty() {
+                        let current = format!("Topic: {}\n", topic.get());
+                        writer.write_all(current.as_bytes()).await.context("Unable to send topic")?;
+                    } else {
+                        tracing::info!(topic = text, "Changing topic");
+                        topic.set(text);
+                    }
+                    line.clear();
+                    continue;
+                }
+                if is_stats(text) {
+                    let current = format!("Stats: {}\n", stats.query().await?);
+                    writer.write_all(current.as_bytes()).await.context("Unable to send stats")?;
+                    line.clear();
+                    continue;
+                }
+                tracing::debug!(bytes = line.len(), "Broadcasting message");
+                connection.message(&peer);
+                tx.send((format!("{peer}: {text}"), peer.clone())).context("Failed to broadcast message from client")?;
+                line.clear();
             },
             Ok((message, source)) = rx.recv() => {
-                if source == addr {
+                if source == peer {
                     continue;
                 }
                 writer.write_all(message.as_bytes()).await.context("Failed to write message to client")?;
             },
             Ok(()) = topic_rx.changed() => {
                 writer.write_all(
-                    format!("Announcement: {}\n", *topic_rx.borrow()).as_bytes()).await.context("Unable to forward topic change")?;
+                    format!("Topic: {}\n", *topic_rx.borrow()).as_bytes()).await.context("Unable to forward topic change")?;
+            }
+            Ok(()) = announcements.changed() => {
+                let text = announcements.borrow().lines().map(|line| format!("Announcement: {line}\n")).collect::<String>();
+                writer.write_all(text.as_bytes()).await.context("Unable to forward announcement")?;
+            }
+            () = sleep_until(idle) => {
+                tracing::info!("Client idle for too long");
+                writer.write_all(b"Disconnected after being idle\n").await.context("Unable to send idle notice")?;
+                break Ok(());
             }
             else => {
                 break Ok(());
@@ -54,7 +136,100 @@ where
     }
 }
 
-/// Periodically send a message on the given `topic` ([`watch::Sender`]).
+/// If `line` is `/topic` or `/topic <text>`, return the (trimmed) text.
+fn topic_command(line: &str) -> Option<&str> {
+    let rest = line.trim_end_matches(['\r', '\n']).strip_prefix("/topic")?;
+    if rest.is_empty() || rest.starts_with(' ') {
+        Some(rest.trim())
+    } else {
+        None
+    }
+}
+
+/// If `line` is `/stats`, return `true`.
+fn is_stats(line: &str) -> bool {
+    line.trim_end_matches(['\r', '\n']) == "/stats"
+}
+
+/// The chat topic shared by all clients, and who may change it.
+#[derive(Debug, Clone)]
+pub struct Topic {
+    tx: Arc<watch::Sender<String>>,
+    setters: Option<Arc<[TopicSetter]>>,
+}
+
+impl Topic {
+    /// Topic starting as `topic`.
+    /// If `setters` is `None`, anyone may change it. Otherwise, only the listed clients may.
+    pub fn new(topic: impl Into<String>, setters: Option<Vec<TopicSetter>>) -> Self {
+        let (tx, _rx) = watch::channel(topic.into());
+        Self {
+            tx: Arc::new(tx),
+            setters: setters.map(Into::into),
+        }
+    }
+
+    /// The current topic.
+    pub fn get(&self) -> String {
+        self.tx.borrow().clone()
+    }
+
+    /// Change the topic, notifying every client.
+    pub fn set(&self, topic: impl Into<String>) {
+        self.tx.send_replace(topic.into());
+    }
+
+    /// A receiver notified of every topic change.
+    pub fn subscribe(&self) -> watch::Receiver<String> {
+        self.tx.subscribe()
+    }
+
+    /// May `peer` change the topic?
+    pub fn may_set(&self, peer: &PeerId) -> bool {
+        let Some(setters) = &self.setters else {
+            return true;
+        };
+        setters.iter().any(|setter| setter.matches(peer))
+    }
+}
+
+/// A client allowed to change the [`Topic`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TopicSetter {
+    /// Every TCP client connecting from this address.
+    Host(IpAddr),
+    /// Only the client with this identity.
+    Peer(PeerId),
+}
+
+impl TopicSetter {
+    /// Is `peer` this setter?
+    fn matches(&self, peer: &PeerId) -> bool {
+        match (self, peer) {
+            (Self::Host(host), PeerId::Tcp(addr)) => addr.ip() == *host,
+            (Self::Host(_), PeerId::Unix(_) | PeerId::Memory(_)) => false,
+            (Self::Peer(setter), peer) => setter == peer,
+        }
+    }
+}
+
+impl FromStr for TopicSetter {
+    type Err = anyhow::Error;
+
+    /// Parse an address without port (`127.0.0.1`, `::1` or `[::1]`), or a full identity (`127.0.0.1:4000`, `unix:0`).
+    fn from_str(setter: &str) -> Result<Self, Self::Err> {
+        let host = setter
+            .strip_prefix('[')
+            .and_then(|host| host.strip_suffix(']'))
+            .unwrap_or(setter);
+        match host.parse() {
+            Ok(host) => Ok(Self::Host(host)),
+            Err(_) => setter.parse().map(Self::Peer),
+        }
+    }
+}
+
+/// Periodically send a message on the given `topic` ([`watch::Sender`]), usually the announcements channel.
 /// The message will be `"Up for Xs"`, where `X` increases in steps given in `duration`.
 /// The interval period is given by `duration`.
 pub async fn announce_uptime(
@@ -73,27 +248,159 @@ pub async fn announce_uptime(
     }
 }
 
+/// When an [`Announcement`] is made.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Schedule {
+    /// Every given period, starting right away.
+    Every(Duration),
+    /// At the (UTC) times matching a cron expression with seconds, such as `0 0 * * * *` for every full hour.
+    Cron(Box<cron::Schedule>),
+}
+
+impl Schedule {
+    /// When to announce first, if the scheduler starts at `now`, which is `wall_clock` in UTC.
+    fn first(&self, now: Instant, wall_clock: DateTime<Utc>) -> Option<Instant> {
+        match self {
+            Self::Every(_) => Some(now),
+            Self::Cron(_) => self.next(now, wall_clock),
+        }
+    }
+
+    /// When to announce next, after `now`, which is `wall_clock` in UTC.
+    fn next(&self, now: Instant, wall_clock: DateTime<Utc>) -> Option<Instant> {
+        match self {
+            Self::Every(period) => Some(now + *period),
+            Self::Cron(schedule) => {
+                let next = schedule.after(&wall_clock).next()?;
+                Some(now + (next - wall_clock).to_std().unwrap_or_default())
+            }
+        }
+    }
+}
+
+/// A text announced according to a [`Schedule`].
+///
+/// The text may contain the placeholders `{uptime}` (like `42s`), `{clients}` (number of connected clients),
+/// `{messages}` (number of messages broadcast so far), `{rate}` (messages in the last minute),
+/// `{busiest}` (client which sent the most of them), `{stats}` (all of the former, see [`crate::stats::Stats`])
+/// and `{time}` (UTC wall clock time).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Announcement {
+    /// Template of the announced text.
+    pub text: String,
+    /// When to announce it.
+    pub schedule: Schedule,
+}
+
+/// Make the announcements in `schedule` on `announcements`, until there are no more receivers.
+/// Announcements due at the same time are sent together, one per line.
+/// Whenever `schedule` changes, start over with the new announcements.
+///
+/// The statistics are queried from `stats`.
+/// `started_at` is the current wall clock time, later times are derived from tokio's clock
+/// (so they are deterministic with a paused clock).
+///
+/// # Termination
+/// When all receivers of `announcements` are dropped, the future terminates.
+///
+/// # Errors
+/// Returns an error if the statistics are gone.
+pub async fn schedule_announcements(
+    mut schedule: watch::Receiver<Vec<Announcement>>,
+    announcements: watch::Sender<String>,
+    stats: Statistics,
+    started_at: DateTime<Utc>,
+) -> anyhow::Result<()> {
+    let started = Instant::now();
+    let wall_clock = |now: Instant| started_at + (now - started);
+
+    let mut current = schedule.borrow_and_update().clone();
+    let mut due = current
+        .iter()
+        .map(|announcement| announcement.schedule.first(started, started_at))
+        .collect::<Vec<_>>();
+    let mut reloadable = true;
+
+    loop {
+        let next = due.iter().flatten().min().copied();
+        tokio::select! {
+            () = tokio::time::sleep_until(next.unwrap_or(started)), if next.is_some() => {
+                let now = next.expect("Only sleeping until the next announcement");
+                let stats = stats.query().await?;
+                let mut texts = Vec::new();
+                for (announcement, due) in current.iter().zip(&mut due) {
+                    if due.is_some_and(|due| due <= now) {
+                        texts.push(render(
+                            &announcement.text,
+                            now - started,
+                            &stats,
+                            wall_clock(now),
+                        ));
+                        *due = announcement.schedule.next(now, wall_clock(now));
+                    }
+                }
+                if announcements.send(texts.join("\n")).is_err() {
+                    // receivers dropped.
+                    break Ok(());
+                }
+            }
+            result = schedule.changed(), if reloadable => match result {
+                Ok(()) => {
+                    current = schedule.borrow_and_update().clone();
+                    let now = Instant::now();
+                    due = current
+                        .iter()
+                        .map(|announcement| announcement.schedule.next(now, wall_clock(now)))
+                        .collect();
+                    tracing::info!(announcements = current.len(), "Reloaded announcements");
+                }
+                // Nobody can change the schedule anymore.
+                Err(_) => reloadable = false,
+            },
+        }
+    }
+}
+
+/// Fill in the placeholders of an [`Announcement`] text.
+fn render(text: &str, uptime: Duration, stats: &Stats, time: DateTime<Utc>) -> String {
+    let busiest = match &stats.busiest {
+        Some((sender, _count)) => sender.as_str(),
+        None => "nobody",
+    };
+    text.replace("{uptime}", &format!("{}s", uptime.as_secs()))
+        .replace("{clients}", &stats.clients.to_string())
+        .replace("{messages}", &stats.messages.to_string())
+        .replace("{rate}", &stats.per_minute.to_string())
+        .replace("{busiest}", busiest)
+        .replace("{stats}", &stats.to_string())
+        .replace("{time}", &time.format("%Y-%m-%d %H:%M:%S UTC").to_string())
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
+    use std::net::SocketAddr;
     use std::time::Duration;
     use tokio_test::io::Builder as Mock;
 
     #[tokio::test]
     async fn broadcasts_message() {
-        let writer = Mock::new().build();
+        let writer = Mock::new().write(b"Topic: Chat topic\n").build();
         let reader = Mock::new().read(b"hello").build();
 
         let (tx, mut rx) = broadcast::channel(16);
-        let (_topic_tx, topic_rx) = watch::channel("Chat topic".to_string());
+        let (_announce_tx, announcements) = watch::channel(String::new());
 
         let handle = tokio::spawn(handle_connection(
-            "127.0.0.3:8081".parse().unwrap(),
+            "127.0.0.3:8081".parse::<SocketAddr>().unwrap(),
             reader,
             writer,
             tx.clone(),
             tx.subscribe(),
-            topic_rx,
+            Topic::new("Chat topic", None),
+            announcements,
+            stats(),
+            Session::default(),
         ));
 
         let (message, socket) = rx.recv().await.unwrap();
@@ -110,20 +417,26 @@ mod test {
 
     #[tokio::test(start_paused = true)]
     async fn receives_message() {
-        let writer = Mock::new().write(b"how's it going").build();
+        let writer = Mock::new()
+            .write(b"Topic: Chat topic\n")
+            .write(b"how's it going")
+            .build();
         // give the writer time to be written before reader is read, returning 'no data', ending handle_connection.
         let reader = Mock::new().wait(Duration::from_secs(1)).build();
 
         let (tx, _rx) = broadcast::channel(1);
-        let (_topic_tx, topic_rx) = watch::channel("Chat topic".to_string());
+        let (_announce_tx, announcements) = watch::channel(String::new());
 
         let handle = tokio::spawn(handle_connection(
-            "127.0.0.3:8081".parse().unwrap(),
+            "127.0.0.3:8081".parse::<SocketAddr>().unwrap(),
             reader,
             writer,
             tx.clone(),
             tx.subscribe(),
-            topic_rx,
+            Topic::new("Chat topic", None),
+            announcements,
+            stats(),
+            Session::default(),
         ));
 
         tx.send((
@@ -135,6 +448,47 @@ mod test {
         tokio::join!(handle).0.unwrap().unwrap();
     }
 
+    #[tokio::test(start_paused = true)]
+    async fn keeps_partial_line_while_forwarding() {
+        let writer = Mock::new()
+            .write(b"Topic: Chat topic\n")
+            .write(b"127.0.0.1:1234: hi\n")
+            .build();
+        let reader = Mock::new()
+            .read(b"hel")
+            .wait(Duration::from_secs(1))
+            .read(b"lo\n")
+            .build();
+
+        let (tx, mut rx) = broadcast::channel(16);
+        let (_announce_tx, announcements) = watch::channel(String::new());
+
+        let handle = tokio::spawn(handle_connection(
+            "127.0.0.3:8081".parse::<SocketAddr>().unwrap(),
+            reader,
+            writer,
+            tx.clone(),
+            tx.subscribe(),
+            Topic::new("Chat topic", None),
+            announcements,
+            stats(),
+            Session::default(),
+        ));
+
+        tokio::time::sleep(Duration::from_millis(500)).await;
+        tx.send((
+            "127.0.0.1:1234: hi\n".to_string(),
+            "127.0.0.1:1234".parse().unwrap(),
+        ))
+        .unwrap();
+
+        rx.recv().await.unwrap();
+        let (message, _) = rx.recv().await.unwrap();
+        assert_eq!(message, "127.0.0.3:8081: hello\n");
+
+        tokio::join!(handle).0.unwrap().unwrap();
+    }
+
     #[tokio::test(start_paused = true)]
     async fn announces_time() {
         let (tx, mut rx) = watch::channel("initial".to_string());
@@ -161,6 +515,7 @@ mod test {
     #[tokio::test(start_paused = true)]
     async fn forwards_announcements_to_clients() {
         let writer = Mock::new()
+            .write(b"Topic: Chat topic\n")
             .write(b"Announcement: hello\n")
             .write(b"Announcement: i'm\n")
             .write(b"Announcement: a\n")
@@ -169,25 +524,328 @@ mod test {
         let reader = Mock::new().wait(Duration::from_secs(1)).build();
 
         let (tx, _rx) = broadcast::channel(16);
-        let (topic_tx, topic_rx) = watch::channel("Discarded initial topic".to_string());
+        let (announce_tx, announcements) =
+            watch::channel("Discarded initial announcement".to_string());
 
         let handle = tokio::spawn(handle_connection(
-            "127.0.0.3:8081".parse().unwrap(),
+            "127.0.0.3:8081".parse::<SocketAddr>().unwrap(),
             reader,
             writer,
             tx.clone(),
             tx.subscribe(),
-            topic_rx,
+            Topic::new("Chat topic", None),
+            announcements,
+            stats(),
+            Session::default(),
         ));
 
-        topic_tx.send("hello".to_string()).unwrap();
+        announce_tx.send("hello".to_string()).unwrap();
         tokio::time::sleep(Duration::from_millis(10)).await;
-        topic_tx.send("i'm".to_string()).unwrap();
+        announce_tx.send("i'm".to_string()).unwrap();
         tokio::time::sleep(Duration::from_millis(10)).await;
-        topic_tx.send("a".to_string()).unwrap();
+        announce_tx.send("a".to_string()).unwrap();
+        tokio::time::sleep(Duration::from_millis(10)).await;
+        announce_tx.send("teapot".to_string()).unwrap();
+
+        tokio::join!(handle).0.unwrap().unwrap();
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn changes_topic() {
+        let writer = Mock::new()
+            .write(b"Topic: Chat topic\n")
+            .write(b"Topic: Rust\n")
+            .write(b"Topic: Rust\n")
+            .build();
+        let reader = Mock::new()
+            .read(b"/topic Rust\r\n")
+            .wait(Duration::from_secs(1))
+            .read(b"/topic\n")
+            .build();
+
+        let (tx, mut rx) = broadcast::channel(16);
+        let (_announce_tx, announcements) = watch::channel(String::new());
+        let topic = Topic::new("Chat topic", None);
+
+        handle_connection(
+            "127.0.0.3:8081".parse::<SocketAddr>().unwrap(),
+            reader,
+            writer,
+            tx.clone(),
+            tx.subscribe(),
+            topic.clone(),
+            announcements,
+            stats(),
+            Session::default(),
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(topic.get(), "Rust");
+        // Commands are not broadcast.
+        assert!(rx.try_recv().is_err());
+    }
+
+    #[tokio::test]
+    async fn rejects_topic_change_without_permission() {
+        let writer = Mock::new()
+            .write(b"Topic: Chat topic\n")
+            .write(b"Not allowed to change the topic\n")
+            .build();
+        let reader = Mock::new().read(b"/topic Rust\n").build();
+
+        let (tx, _rx) = broadcast::channel(16);
+        let (_announce_tx, announcements) = watch::channel(String::new());
+        let topic = Topic::new("Chat topic", Some(vec!["127.0.0.1".parse().unwrap()]));
+
+        handle_connection(
+            "127.0.0.3:8081".parse::<SocketAddr>().unwrap(),
+            reader,
+            writer,
+            tx.clone(),
+            tx.subscribe(),
+            topic.clone(),
+            announcements,
+            stats(),
+            Session::default(),
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(topic.get(), "Chat topic");
+    }
+
+    #[test]
+    fn matches_topic_setters() {
+        let setters = ["127.0.0.1", "[::1]:4000", "::2", "[::3]", "unix:1"];
+        let topic = Topic::new(
+            "",
+            Some(
+                setters
+                    .iter()
+                    .map(|setter| setter.parse().unwrap())
+                    .collect(),
+            ),
+        );
+        let may_set = |peer: &str| topic.may_set(&peer.parse().unwrap());
+        assert!(may_set("127.0.0.1:1234"));
+        assert!(may_set("[::1]:4000"));
+        assert!(!may_set("[::1]:4001"));
+        assert!(may_set("[::2]:4000"));
+        assert!(may_set("[::3]:4000"));
+        assert!(!may_set("127.0.0.2:1234"));
+        assert!(may_set("unix:1"));
+        assert!(!may_set("unix:2"));
+        assert!(!may_set("memory:1"));
+
+        let anyone = "127.0.0.1:1234".parse().unwrap();
+        assert!(!Topic::new("", Some(Vec::new())).may_set(&anyone));
+        assert!(Topic::new("", None).may_set(&anyone));
+        assert!(Topic::new("", None).may_set(&PeerId::Memory(0)));
+    }
+
+    #[test]
+    fn parses_topic_setters() {
+        assert_eq!(
+            "10.0.0.1".parse::<TopicSetter>().unwrap(),
+            TopicSetter::Host("10.0.0.1".parse().unwrap())
+        );
+        assert_eq!(
+            "[::1]".parse::<TopicSetter>().unwrap(),
+            TopicSetter::Host("::1".parse().unwrap())
+        );
+        assert_eq!(
+            "memory:2".parse::<TopicSetter>().unwrap(),
+            TopicSetter::Peer(PeerId::Memory(2))
+        );
+        assert!("localhost".parse::<TopicSetter>().is_err());
+    }
+
+    #[test]
+    fn parses_topic_command() {
+        assert_eq!(topic_command("/topic  Rust \r\n"), Some("Rust"));
+        assert_eq!(topic_command("/topic\n"), Some(""));
+        assert_eq!(topic_command("/topics\n"), None);
+        assert_eq!(topic_command("hello /topic\n"), None);
+    }
+
+    /// A running statistics actor.
+    fn stats() -> Statistics {
+        let (stats, rx) = crate::stats::channel();
+        tokio::spawn(crate::stats::collect(rx));
+        stats
+    }
+
+    /// Start the scheduler at 2024-01-01 00:00:58 UTC.
+    fn start_scheduler(
+        schedule: watch::Receiver<Vec<Announcement>>,
+    ) -> (watch::Receiver<String>, Statistics) {
+        let (announce_tx, announcements) = watch::channel(String::new());
+        let stats = stats();
+        let started_at = "2024-01-01T00:00:58Z".parse().unwrap();
+        tokio::spawn(schedule_announcements(
+            schedule,
+            announce_tx,
+            stats.clone(),
+            started_at,
+        ));
+        (announcements, stats)
+    }
+
+    async fn next_announcement(announcements: &mut watch::Receiver<String>) -> String {
+        announcements.changed().await.unwrap();
+        announcements.borrow_and_update().clone()
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn fills_in_templates() {
+        let (_schedule_tx, schedule) = watch::channel(vec![Announcement {
+            text: "Up for {uptime}, {clients} client(s), {messages} message(s)".to_string(),
+            schedule: Schedule::Every(Duration::from_secs(10)),
+        }]);
+        let (mut announcements, stats) = start_scheduler(schedule);
+        let connection = stats.connect();
+
+        assert_eq!(
+            next_announcement(&mut announcements).await,
+            "Up for 0s, 1 client(s), 0 message(s)"
+        );
+        connection.message(&"127.0.0.1:1");
+        assert_eq!(
+            next_announcement(&mut announcements).await,
+            "Up for 10s, 1 client(s), 1 message(s)"
+        );
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn announces_statistics() {
+        let (_schedule_tx, schedule) = watch::channel(vec![Announcement {
+            text: "{rate} per minute, busiest: {busiest}".to_string(),
+            schedule: Schedule::Every(Duration::from_secs(10)),
+        }]);
+        let (mut announcements, stats) = start_scheduler(schedule);
+        let connection = stats.connect();
+        connection.message(&"127.0.0.1:1");
+        connection.message(&"127.0.0.1:1");
+
+        assert_eq!(
+            next_announcement(&mut announcements).await,
+            "2 per minute, busiest: 127.0.0.1:1"
+        );
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn announces_on_cron_schedule() {
+        let (_schedule_tx, schedule) = watch::channel(vec![Announcement {
+            text: "It is {time}".to_string(),
+            schedule: Schedule::Cron(Box::new("0 * * * * *".parse().unwrap())),
+        }]);
+        let (mut announcements, _tx) = start_scheduler(schedule);
+        let started = Instant::now();
+
+        assert_eq!(
+            next_announcement(&mut announcements).await,
+            "It is 2024-01-01 00:01:00 UTC"
+        );
+        assert_eq!(started.elapsed(), Duration::from_secs(2));
+        assert_eq!(
+            next_announcement(&mut announcements).await,
+            "It is 2024-01-01 00:02:00 UTC"
+        );
+        assert_eq!(started.elapsed(), Duration::from_secs(62));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn sends_simultaneous_announcements_together() {
+        let every = |text: &str, secs| Announcement {
+            text: text.to_string(),
+            schedule: Schedule::Every(Duration::from_secs(secs)),
+        };
+        let (_schedule_tx, schedule) = watch::channel(vec![every("a", 2), every("b", 3)]);
+        let (mut announcements, _tx) = start_scheduler(schedule);
+
+        assert_eq!(next_announcement(&mut announcements).await, "a\nb");
+        assert_eq!(next_announcement(&mut announcements).await, "a");
+        assert_eq!(next_announcement(&mut announcements).await, "b");
+        assert_eq!(next_announcement(&mut announcements).await, "a");
+        assert_eq!(next_announcement(&mut announcements).await, "a\nb");
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn reloads_schedule() {
+        let (schedule_tx, schedule) = watch::channel(vec![Announcement {
+            text: "old".to_string(),
+            schedule: Schedule::Every(Duration::from_secs(60)),
+        }]);
+        let (mut announcements, _tx) = start_scheduler(schedule);
+        assert_eq!(next_announcement(&mut announcements).await, "old");
+
+        let started = Instant::now();
+        schedule_tx.send_replace(vec![Announcement {
+            text: "new".to_string(),
+            schedule: Schedule::Every(Duration::from_secs(1)),
+        }]);
+        assert_eq!(next_announcement(&mut announcements).await, "new");
+        assert_eq!(started.elapsed(), Duration::from_secs(1));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn forwards_multi_line_announcements() {
+        let writer = Mock::new()
+            .write(b"Topic: Chat topic\n")
+            .write(b"Announcement: a\nAnnouncement: b\n")
+            .build();
+        let reader = Mock::new().wait(Duration::from_secs(1)).build();
+
+        let (tx, _rx) = broadcast::channel(16);
+        let (announce_tx, announcements) = watch::channel(String::new());
+
+        let handle = tokio::spawn(handle_connection(
+            "127.0.0.3:8081".parse::<SocketAddr>().unwrap(),
+            reader,
+            writer,
+            tx.clone(),
+            tx.subscribe(),
+            Topic::new("Chat topic", None),
+            announcements,
+            stats(),
+            Session::default(),
+        ));
         tokio::time::sleep(Duration::from_millis(10)).await;
-        topic_tx.send("teapot".to_string()).unwrap();
+        announce_tx.send("a\nb".to_string()).unwrap();
 
         tokio::join!(handle).0.unwrap().unwrap();
     }
+
+    #[tokio::test]
+    async fn answers_stats_command() {
+        let writer = Mock::new()
+            .write(b"Topic: Chat topic\n")
+            .write(
+                b"Stats: 1 client(s) connected, 1 message(s) per minute, busiest sender: 127.0.0.3:8081 (1)\n",
+            )
+            .build();
+        let reader = Mock::new().read(b"hello\n").read(b"/stats\r\n").build();
+
+        let (tx, mut rx) = broadcast::channel(16);
+        let (_announce_tx, announcements) = watch::channel(String::new());
+
+        handle_connection(
+            "127.0.0.3:8081".parse::<SocketAddr>().unwrap(),
+            reader,
+            writer,
+            tx.clone(),
+            tx.subscribe(),
+            Topic::new("Chat topic", None),
+            announcements,
+            stats(),
+            Session::default(),
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(rx.recv().await.unwrap().0, "127.0.0.3:8081: hello\n");
+        // Commands are not broadcast.
+        assert!(rx.try_recv().is_err());
+    }
 }
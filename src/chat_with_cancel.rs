@@ -1,61 +1,127 @@
 use anyhow::Context;
-use std::net::SocketAddr;
+use std::{
+    collections::HashMap,
+    fmt::{Debug, Display},
+    hash::Hash,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
 use tokio::{
-    io::{AsyncBufReadExt, AsyncRead, AsyncWrite, AsyncWriteExt, BufReader},
+    io::{AsyncRead, AsyncWrite, AsyncWriteExt, BufReader},
     sync::broadcast,
+    time::Instant,
 };
 use tokio_util::sync::CancellationToken;
 
+use crate::{read_bounded_line, sleep_until, Config, Session};
+
 /// Monitor the `reader` and the `rx` for messages.
 /// When receiving bytes on `reader`, forward them on the [`broadcast::Sender`].
-/// When receiving a message on `rx`, where the source socket address is not our own,
+/// When receiving a message on `rx`, where the source `peer` is not our own,
 /// forward it on `writer` (else, discard it).
 ///
 /// # Termination
 /// If an error or `None` is encountered, the future terminates.
 /// If EOF is signalled on `reader` by `Ok(0)`, the future terminates.
-/// If the text read from `reader` is `"quit"` or `"quit\n"` or `"quit\r\n"`, the future terminates.
-/// If the text read from `reader` is `"call it a day"` or `"call it a day\n"` or `"call it a day\r\n"`,
-/// the [`CancellationToken`] is triggered.
-/// If the `token` is cancelled somewhere else, the future terminates.
-pub async fn handle_connection<Reader, Writer>(
-    addr: SocketAddr,
+/// If a line read from `reader` exceeds [`MAX_LINE`](crate::MAX_LINE) bytes, the future fails.
+/// If the line read from `reader` is the quit phrase of the `session` (see [`Session::is_quit`]), the future terminates.
+/// If no line is read from `reader` within the idle timeout of the `session`, the future terminates.
+/// If the text read from `reader` is the termination phrase followed by the secret (see [`Termination`]),
+/// everyone is told who requested the shutdown, and the root token of the `registry` is cancelled, shutting down every client.
+/// Requests without the right secret are rejected and not broadcast.
+/// If the client's token in the `registry` is cancelled by [`Registry::cancel`], the future terminates.
+/// When the root token is cancelled, the client is told that the server shuts down within the grace period,
+/// and is served as before until the grace period ends.
+#[tracing::instrument(name = "connection", skip_all, fields(peer = %peer))]
+#[allow(clippy::too_many_arguments)]
+pub async fn handle_connection<Reader, Writer, Peer>(
+    peer: Peer,
     reader: Reader,
     mut writer: Writer,
-    tx: broadcast::Sender<(String, SocketAddr)>,
-    mut rx: broadcast::Receiver<(String, SocketAddr)>,
-    token: CancellationToken,
+    tx: broadcast::Sender<(String, Peer)>,
+    mut rx: broadcast::Receiver<(String, Peer)>,
+    registry: Registry<Peer>,
+    termination: Termination,
+    session: Session,
 ) -> anyhow::Result<()>
 where
     Reader: AsyncRead + Unpin,
     Writer: AsyncWrite + Unpin,
+    Peer: Display + Debug + Eq + Hash + Clone + Send + Sync + 'static,
 {
-    let mut line = String::new();
+    let client = registry.register(peer.clone());
+    // Unlike `read_line`, `read_bounded_line` is cancel safe: bytes read before another branch completes stay in `line`.
+    let mut line = Vec::new();
     let mut reader = BufReader::new(reader);
+    let mut idle = session.idle_deadline();
+    // Once the server shuts down: when the grace period ends, if ever.
+    let mut shutting_down = false;
+    let mut grace_period_end = None;
 
     loop {
         tokio::select! {
-            Ok(bytes_read) = reader.read_line(&mut line) => {
-                if bytes_read == 0 {
+            result = read_bounded_line(&mut reader, &mut line) => {
+                if let Err(e) = result {
+                    break Err(e).context("Failed to read line");
+                }
+                if line.is_empty() {
+                    tracing::info!("Client disconnected");
                     break Ok::<(), anyhow::Error>(()); // EOF detected.
                 }
-                if is_termination_message(&line) {
-                    token.cancel();
+                idle = session.idle_deadline();
+                let Ok(text) = std::str::from_utf8(&line) else {
+                    break Err(anyhow::anyhow!("Received invalid UTF-8"));
+                };
+                match termination.check(text) {
+                    Some(true) => {
+                        tracing::warn!(target: "achat::audit", %peer, "Shutdown requested");
+                        tx.send((format!("Shutdown requested by {peer}\n"), peer.clone())).context("Failed to announce shutdown")?;
+                        registry.shutdown();
+                        line.clear();
+                        continue;
+                    }
+                    Some(false) => {
+                        tracing::warn!(target: "achat::audit", %peer, "Shutdown request rejected");
+                        writer.write_all(b"Shutdown not permitted\n").await.context("Failed to reject shutdown")?;
+                        line.clear();
+                        continue;
+                    }
+                    None => {}
                 }
-                if line == "quit" || line == "quit\r\n" {
+                if session.is_quit(text) {
+                    tracing::info!("Client quit");
                     break Ok(());
                 }
-                tx.send((format!("{addr}: {line}"), addr)).context("Failed to broadcast message")?;
+                tracing::debug!(bytes = line.len(), "Broadcasting message");
+                tx.send((format!("{peer}: {text}"), peer.clone())).context("Failed to broadcast message")?;
+                line.clear();
             },
             Ok((message, source)) = rx.recv() => {
-                if source == addr {
+                if source == peer {
                     continue;
                 }
                 writer.write_all(message.as_bytes()).await.context("Failed to forward message")?;
             }
-            _ = token.cancelled() => {
-                break Ok(());
+            _ = client.token().cancelled(), if !shutting_down => {
+                if !registry.token().is_cancelled() {
+                    tracing::info!("Client cancelled");
+                    break Ok(());
+                }
+                tracing::info!("Shutting down client");
+                let notice = format!("Server shutting down in {}\n", seconds(termination.grace_period));
+                writer.write_all(notice.as_bytes()).await.context("Failed to send shutdown notice")?;
+                shutting_down = true;
+                grace_period_end = Instant::now().checked_add(termination.grace_period);
             },
+            () = sleep_until(grace_period_end) => {
+                tracing::info!("Grace period over");
+                break Ok(());
+            }
+            () = sleep_until(idle) => {
+                tracing::info!("Client idle for too long");
+                writer.write_all(b"Disconnected after being idle\n").await.context("Failed to send idle notice")?;
+                break Ok(());
+            }
             else => {
                 break Ok(());
             }
@@ -69,16 +135,182 @@ where
         .context("Unable to shut down client writer")
 }
 
-/// If the `line` is `"call it a day"` or `"call it a day\n"` or `"call it a day\r\n"`, return `true`.
-fn is_termination_message(line: &str) -> bool {
-    line == "call it a day" || line == "call it a day\n" || line == "call it a day\r\n"
+/// `duration` in whole seconds, such as `1 second` or `5 seconds`.
+fn seconds(duration: Duration) -> String {
+    match duration.as_secs() {
+        1 => "1 second".to_string(),
+        secs => format!("{secs} seconds"),
+    }
+}
+
+/// Cancellation tokens of the connected clients, each a child of one root token.
+///
+/// Cancelling the root shuts down every client, cancelling a single client's token only that client.
+#[derive(Debug, Clone)]
+pub struct Registry<Peer> {
+    root: CancellationToken,
+    clients: Arc<Mutex<HashMap<Peer, CancellationToken>>>,
+}
+
+impl<Peer> Default for Registry<Peer> {
+    fn default() -> Self {
+        Self::new(CancellationToken::new())
+    }
+}
+
+impl<Peer> Registry<Peer> {
+    /// Registry of clients whose tokens are children of `root`.
+    pub fn new(root: CancellationToken) -> Self {
+        Self {
+            root,
+            clients: Arc::default(),
+        }
+    }
+
+    /// The root token, cancelled when the whole server shuts down.
+    pub fn token(&self) -> CancellationToken {
+        self.root.clone()
+    }
+
+    /// Cancel the root token, shutting down every client.
+    pub fn shutdown(&self) {
+        self.root.cancel();
+    }
+}
+
+impl<Peer: Eq + Hash + Clone> Registry<Peer> {
+    /// Register `peer` with a new child token. It is deregistered when the returned [`Registration`] is dropped.
+    pub fn register(&self, peer: Peer) -> Registration<Peer> {
+        let token = self.root.child_token();
+        self.lock().insert(peer.clone(), token.clone());
+        Registration {
+            registry: self.clone(),
+            peer,
+            token,
+        }
+    }
+
+    /// Cancel the token of `peer`, shutting down only that client.
+    /// Returns `false` if no such client is registered.
+    pub fn cancel(&self, peer: &Peer) -> bool {
+        match self.lock().get(peer) {
+            Some(token) => {
+                token.cancel();
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// The currently registered clients.
+    pub fn peers(&self) -> Vec<Peer> {
+        self.lock().keys().cloned().collect()
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, HashMap<Peer, CancellationToken>> {
+        // The map stays consistent even if a holder of the lock panicked.
+        self.clients
+            .lock()
+            .unwrap_or_else(std::sync::PoisonError::into_inner)
+    }
+}
+
+/// A client's entry in a [`Registry`], removed again on drop.
+#[derive(Debug)]
+pub struct Registration<Peer: Eq + Hash + Clone> {
+    registry: Registry<Peer>,
+    peer: Peer,
+    token: CancellationToken,
+}
+
+impl<Peer: Eq + Hash + Clone> Registration<Peer> {
+    /// The client's token, cancelled by [`Registry::cancel`] or along with the root token.
+    pub fn token(&self) -> &CancellationToken {
+        &self.token
+    }
+}
+
+impl<Peer: Eq + Hash + Clone> Drop for Registration<Peer> {
+    fn drop(&mut self) {
+        let mut clients = self.registry.lock();
+        // A later connection may have registered under the same identity.
+        if clients
+            .get(&self.peer)
+            .is_some_and(|token| *token == self.token)
+        {
+            clients.remove(&self.peer);
+        }
+    }
+}
+
+/// How clients may shut down the server: by sending the `phrase`, a space and the `secret` on one line.
+#[derive(Clone)]
+pub struct Termination {
+    /// Phrase starting a shutdown request.
+    pub phrase: String,
+    /// Shared secret authorizing a shutdown. If `None`, every request is rejected.
+    pub secret: Option<String>,
+    /// Time clients get to finish after shutdown, announced to them.
+    pub grace_period: Duration,
+}
+
+impl Termination {
+    /// Termination as configured in the `[chat_with_cancel]` and `[server]` sections.
+    pub fn from_config(config: &Config) -> Self {
+        Self {
+            phrase: config.chat_with_cancel.termination_phrase.clone(),
+            secret: config.chat_with_cancel.secret.clone(),
+            grace_period: config.server.grace_period(),
+        }
+    }
+
+    /// Is `line` a shutdown request? Returns `None` if not, otherwise whether it is authorized.
+    fn check(&self, line: &str) -> Option<bool> {
+        let rest = line
+            .trim_end_matches(['\r', '\n'])
+            .strip_prefix(self.phrase.as_str())?;
+        if !rest.is_empty() && !rest.starts_with(' ') {
+            // Just a message starting with the phrase.
+            return None;
+        }
+        let token = rest.trim_start_matches(' ');
+        Some(
+            self.secret
+                .as_deref()
+                .is_some_and(|secret| constant_time_eq(secret.as_bytes(), token.as_bytes())),
+        )
+    }
+}
+
+impl Debug for Termination {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Termination")
+            .field("phrase", &self.phrase)
+            .field("secret", &self.secret.as_ref().map(|_| "<redacted>"))
+            .field("grace_period", &self.grace_period)
+            .finish()
+    }
+}
+
+/// Compare `a` and `b` in time depending only on their lengths, so guessing the secret byte by byte does not work.
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |acc, (x, y)| acc | (x ^ y)) == 0
 }
+
 #[cfg(test)]
 mod test {
     use super::*;
-    use std::time::Duration;
+    use std::net::SocketAddr;
     use tokio_test::io::Builder as Mock;
 
+    fn termination(grace_period: Duration) -> Termination {
+        Termination {
+            phrase: "call it a day".to_string(),
+            secret: Some("s3cret".to_string()),
+            grace_period,
+        }
+    }
+
     #[tokio::test]
     async fn broadcasts_message() {
         let writer = Mock::new().build();
@@ -86,15 +318,15 @@ mod test {
 
         let (tx, mut rx) = broadcast::channel(16);
 
-        let token = CancellationToken::new();
-
         let handle = tokio::spawn(handle_connection(
-            "127.0.0.3:8081".parse().unwrap(),
+            "127.0.0.3:8081".parse::<SocketAddr>().unwrap(),
             reader,
             writer,
             tx.clone(),
             tx.subscribe(),
-            token,
+            Registry::default(),
+            termination(Duration::from_secs(1)),
+            Session::default(),
         ));
 
         let (message, socket) = rx.recv().await.unwrap();
@@ -117,15 +349,15 @@ mod test {
 
         let (tx, _rx) = broadcast::channel(1);
 
-        let token = CancellationToken::new();
-
         let handle = tokio::spawn(handle_connection(
-            "127.0.0.3:8081".parse().unwrap(),
+            "127.0.0.3:8081".parse::<SocketAddr>().unwrap(),
             reader,
             writer,
             tx.clone(),
             tx.subscribe(),
-            token,
+            Registry::default(),
+            termination(Duration::from_secs(1)),
+            Session::default(),
         ));
 
         tx.send((
@@ -136,4 +368,201 @@ mod test {
 
         tokio::join!(handle).0.unwrap().unwrap();
     }
+
+    #[tokio::test(start_paused = true)]
+    async fn keeps_partial_line_while_forwarding() {
+        let writer = Mock::new().write(b"127.0.0.1:1234: hi\n").build();
+        let reader = Mock::new()
+            .read(b"hel")
+            .wait(Duration::from_secs(1))
+            .read(b"lo\n")
+            .build();
+
+        let (tx, mut rx) = broadcast::channel(16);
+
+        let handle = tokio::spawn(handle_connection(
+            "127.0.0.3:8081".parse::<SocketAddr>().unwrap(),
+            reader,
+            writer,
+            tx.clone(),
+            tx.subscribe(),
+            Registry::default(),
+            termination(Duration::from_secs(1)),
+            Session::default(),
+        ));
+
+        tokio::time::sleep(Duration::from_millis(500)).await;
+        tx.send((
+            "127.0.0.1:1234: hi\n".to_string(),
+            "127.0.0.1:1234".parse().unwrap(),
+        ))
+        .unwrap();
+
+        rx.recv().await.unwrap();
+        let (message, _) = rx.recv().await.unwrap();
+        assert_eq!(message, "127.0.0.3:8081: hello\n");
+
+        tokio::join!(handle).0.unwrap().unwrap();
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn cancels_single_client() {
+        let registry = Registry::default();
+        let (tx, _rx) = broadcast::channel(16);
+
+        let spawn = |peer: &str, writer| {
+            tokio::spawn(handle_connection(
+                peer.parse::<SocketAddr>().unwrap(),
+                Mock::new().wait(Duration::from_secs(10)).build(),
+                writer,
+                tx.clone(),
+                tx.subscribe(),
+                registry.clone(),
+                termination(Duration::from_secs(1)),
+                Session::default(),
+            ))
+        };
+        let kicked = spawn("127.0.0.1:1", Mock::new().build());
+        let other = spawn(
+            "127.0.0.1:2",
+            Mock::new()
+                .write(b"Server shutting down in 1 second\n")
+                .build(),
+        );
+        tokio::task::yield_now().await;
+        assert_eq!(registry.peers().len(), 2);
+
+        assert!(registry.cancel(&"127.0.0.1:1".parse().unwrap()));
+        kicked.await.unwrap().unwrap();
+        assert!(!other.is_finished());
+        assert_eq!(registry.peers(), vec!["127.0.0.1:2".parse().unwrap()]);
+        assert!(!registry.cancel(&"127.0.0.1:1".parse().unwrap()));
+
+        registry.shutdown();
+        other.await.unwrap().unwrap();
+        assert!(registry.peers().is_empty());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn serves_until_grace_period_ends() {
+        let registry = Registry::default();
+        let (tx, _rx) = broadcast::channel(16);
+        registry.shutdown();
+
+        let writer = Mock::new()
+            .write(b"Server shutting down in 2 seconds\n")
+            .write(b"127.0.0.1:2: bye\n")
+            .build();
+        let reader = Mock::new().wait(Duration::from_secs(10)).build();
+        let start = Instant::now();
+        let handle = tokio::spawn(handle_connection(
+            "127.0.0.1:1".parse::<SocketAddr>().unwrap(),
+            reader,
+            writer,
+            tx.clone(),
+            tx.subscribe(),
+            registry,
+            termination(Duration::from_secs(2)),
+            Session::default(),
+        ));
+
+        tokio::time::sleep(Duration::from_secs(1)).await;
+        tx.send((
+            "127.0.0.1:2: bye\n".to_string(),
+            "127.0.0.1:2".parse().unwrap(),
+        ))
+        .unwrap();
+
+        handle.await.unwrap().unwrap();
+        assert_eq!(start.elapsed(), Duration::from_secs(2));
+    }
+
+    #[test]
+    fn pluralises_seconds() {
+        assert_eq!(seconds(Duration::from_secs(1)), "1 second");
+        assert_eq!(seconds(Duration::from_secs(0)), "0 seconds");
+        assert_eq!(seconds(Duration::from_millis(5500)), "5 seconds");
+    }
+
+    #[test]
+    fn keeps_newer_registration_of_same_peer() {
+        let registry = Registry::default();
+        let old = registry.register("peer");
+        let new = registry.register("peer");
+        drop(old);
+        assert!(registry.cancel(&"peer"));
+        assert!(new.token().is_cancelled());
+    }
+
+    #[test]
+    fn checks_termination_requests() {
+        let termination = termination(Duration::from_secs(1));
+        assert_eq!(termination.check("call it a day s3cret\r\n"), Some(true));
+        assert_eq!(termination.check("call it a day s3cret"), Some(true));
+        assert_eq!(termination.check("call it a day wrong\n"), Some(false));
+        assert_eq!(termination.check("call it a day\n"), Some(false));
+        assert_eq!(termination.check("call it a daydream\n"), None);
+        assert_eq!(termination.check("hello\n"), None);
+
+        let disabled = Termination {
+            secret: None,
+            ..termination
+        };
+        assert_eq!(disabled.check("call it a day s3cret\n"), Some(false));
+    }
+
+    #[tokio::test]
+    async fn rejects_shutdown_without_secret() {
+        let registry = Registry::default();
+        let (tx, mut rx) = broadcast::channel(16);
+
+        let writer = Mock::new().write(b"Shutdown not permitted\n").build();
+        let reader = Mock::new().read(b"call it a day guess\n").build();
+        handle_connection(
+            "127.0.0.1:1".parse::<SocketAddr>().unwrap(),
+            reader,
+            writer,
+            tx.clone(),
+            tx.subscribe(),
+            registry.clone(),
+            termination(Duration::from_secs(1)),
+            Session::default(),
+        )
+        .await
+        .unwrap();
+
+        assert!(!registry.token().is_cancelled());
+        assert!(rx.try_recv().is_err());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn announces_who_requested_shutdown() {
+        let registry = Registry::default();
+        let (tx, mut rx) = broadcast::channel(16);
+
+        let writer = Mock::new()
+            .write(b"Server shutting down in 1 second\n")
+            .build();
+        let reader = Mock::new()
+            .read(b"call it a day s3cret\r\n")
+            .wait(Duration::from_secs(10))
+            .build();
+        handle_connection(
+            "127.0.0.1:1".parse::<SocketAddr>().unwrap(),
+            reader,
+            writer,
+            tx.clone(),
+            tx.subscribe(),
+            registry.clone(),
+            termination(Duration::from_secs(1)),
+            Session::default(),
+        )
+        .await
+        .unwrap();
+
+        assert!(registry.token().is_cancelled());
+        let (message, _) = rx.try_recv().unwrap();
+        assert_eq!(message, "Shutdown requested by 127.0.0.1:1\n");
+        assert!(rx.try_recv().is_err());
+    }
 }
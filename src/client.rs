@@ -0,0 +1,50 @@
+use anyhow::Context;
+use bytes::BytesMut;
+use futures::{SinkExt, StreamExt};
+use std::net::SocketAddr;
+use tokio::{io::AsyncWriteExt, net::TcpStream};
+use tokio_util::codec::{BytesCodec, FramedRead, FramedWrite};
+
+/// Open a TCP connection to `address`, forwarding `stdin` to it and everything received to `stdout`.
+///
+/// # Termination
+/// When either `stdin` or the connection is closed, terminate the future.
+///
+/// # Errors
+/// Returns an error if connecting, reading or writing fails.
+pub async fn connect(address: SocketAddr) -> anyhow::Result<()> {
+    let stdin = FramedRead::new(tokio::io::stdin(), BytesCodec::new());
+    let mut stdin = stdin.map(|i| i.map(BytesMut::freeze));
+    let mut stdout = FramedWrite::new(tokio::io::stdout(), BytesCodec::new());
+
+    let mut stream = TcpStream::connect(address)
+        .await
+        .with_context(|| format!("Failed to connect to {address}"))?;
+    let (reader, writer) = stream.split();
+    let mut sink = FramedWrite::new(writer, BytesCodec::new());
+    let mut stream = FramedRead::new(reader, BytesCodec::new());
+
+    loop {
+        tokio::select! {
+            msg = stream.next() => {
+                if let Some(Ok(msg)) = msg {
+                    stdout.send(msg).await.context("Failed to write to stdout")?;
+                } else {
+                    break
+                }
+            },
+            input = stdin.next() => {
+                if let Some(Ok(input)) = input {
+                    sink.send(input).await.context("Failed to send to server")?;
+                } else {
+                    break;
+                }
+            }
+        }
+    }
+    println!("Done");
+    tokio::io::stdout()
+        .flush()
+        .await
+        .context("Failed to flush stdout")
+}
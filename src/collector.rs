@@ -1,10 +1,34 @@
-use anyhow::Context;
-use std::collections::HashMap;
+use anyhow::{bail, Context};
+use chrono::{DateTime, SecondsFormat, Utc};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{hash_map, BTreeMap, HashMap, VecDeque},
+    fmt::{self, Display},
+    io::ErrorKind,
+    path::{Path, PathBuf},
+    str::FromStr,
+    time::Duration,
+};
 use tokio::{
-    io::{AsyncBufReadExt, AsyncRead, AsyncWrite, AsyncWriteExt, BufReader},
-    sync::{mpsc, oneshot},
+    io::{AsyncRead, AsyncWrite, AsyncWriteExt, BufReader},
+    sync::{
+        broadcast::{self, error::RecvError},
+        mpsc, oneshot,
+    },
+    time::{Instant, MissedTickBehavior},
 };
 
+use crate::read_bounded_line;
+
+/// HTTP access to the collector, see [`http::router`].
+pub mod http;
+mod search;
+
+pub use search::Match;
+
+/// Most matches answered to a search.
+const SEARCH_LIMIT: usize = 20;
+
 /// A message sent from a client to the server.
 #[derive(Debug)]
 pub enum Message {
@@ -18,19 +42,316 @@ pub enum Message {
 
     /// Request for the current message report.
     Report {
+        /// Which messages to report.
+        query: Query,
+        /// A oneshot channel for sending the rendered report, or why it could not be rendered.
+        reply: oneshot::Sender<Result<String, String>>,
+    },
+
+    /// Request to snapshot the collected messages right away.
+    Snapshot {
+        /// A oneshot channel for sending the outcome.
+        reply: oneshot::Sender<String>,
+    },
+
+    /// Request for the retention state: what is kept and what was evicted.
+    Retention {
         /// A oneshot channel for sending the reply.
         reply: oneshot::Sender<String>,
     },
+
+    /// Request for the senders and how many of their messages are kept.
+    Senders {
+        /// A oneshot channel for sending the reply.
+        reply: oneshot::Sender<BTreeMap<String, usize>>,
+    },
+
+    /// Request for the [`SenderStats`] of each sender.
+    Stats {
+        /// A oneshot channel for sending the reply.
+        reply: oneshot::Sender<BTreeMap<String, SenderStats>>,
+    },
+
+    /// Request for the collected messages best matching some search terms, see [`Match`].
+    Search {
+        /// The search terms.
+        terms: String,
+        /// A oneshot channel for sending the matches.
+        reply: oneshot::Sender<Vec<Match>>,
+    },
+
+    /// Request to receive every message collected from now on.
+    Follow {
+        /// A oneshot channel for sending the receiver of the collected messages.
+        reply: oneshot::Sender<broadcast::Receiver<Collected>>,
+    },
+
+    /// Request to claim a name for the messages of a connection.
+    Hello {
+        /// The name to claim.
+        name: String,
+        /// A oneshot channel for sending the claim, or `None` if another connection holds the name.
+        reply: oneshot::Sender<Option<Claim>>,
+    },
+
+    /// Request whether a live connection holds a name, without claiming it.
+    Claimed {
+        /// The name to look up.
+        name: String,
+        /// A oneshot channel for sending whether the name is held.
+        reply: oneshot::Sender<bool>,
+    },
+}
+
+/// A connection's claim on a name, see [`Message::Hello`]. Dropping it frees the name again.
+#[derive(Debug)]
+pub struct Claim {
+    /// Closed when the claim is dropped, which the collector notices on the other end.
+    _held: oneshot::Receiver<()>,
+}
+
+/// Longest name a connection can claim, in bytes.
+const MAX_NAME_LENGTH: usize = 64;
+
+/// A newly collected message, as streamed to followers.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Collected {
+    /// Origin of message.
+    pub sender: String,
+    /// When the message was received.
+    pub at: DateTime<Utc>,
+    /// Message content.
+    pub content: String,
+}
+
+impl Display for Collected {
+    /// The message as a line of a [`ReportFormat::Text`] report.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        Row::new(&self.sender, self.at, &self.content).fmt(f)
+    }
 }
 
-/// Receive messages on reader. When receiving `"report"` or `"report\n"` or `"report\r\n"`,
+/// Capacity of the channel streaming collected messages to followers.
+/// Followers falling further behind miss messages, and are told how many.
+const FOLLOW_CAPACITY: usize = 256;
+
+/// Which messages to include in a report, and how to format it, given as
+/// `report [sender=<name>] [last=<n>] [since=<timestamp>] [grep=<text>] [format=<format>]`.
+///
+/// All given conditions must hold for a message to be included.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Query {
+    /// Only messages of this sender.
+    pub sender: Option<String>,
+    /// Only the last this many matching messages, over all senders.
+    pub last: Option<usize>,
+    /// Only messages received at or after this time, given in RFC 3339 (`2024-01-01T12:00:00Z`).
+    pub since: Option<DateTime<Utc>>,
+    /// Only messages containing this text.
+    pub grep: Option<String>,
+    /// How to format the report.
+    pub format: ReportFormat,
+}
+
+impl FromStr for Query {
+    type Err = anyhow::Error;
+
+    /// Parse the whitespace separated `key=value` arguments of a report request.
+    fn from_str(arguments: &str) -> Result<Self, Self::Err> {
+        let mut query = Self::default();
+        for argument in arguments.split_whitespace() {
+            let Some((key, value)) = argument.split_once('=') else {
+                bail!("expected key=value, got {argument:?}");
+            };
+            query.set(key, value)?;
+        }
+        Ok(query)
+    }
+}
+
+impl Query {
+    /// Set the condition or format named `key` to `value`.
+    ///
+    /// # Errors
+    /// Returns an error if the key is unknown or the value invalid.
+    pub fn set(&mut self, key: &str, value: &str) -> anyhow::Result<()> {
+        match key {
+            "sender" => self.sender = Some(value.to_string()),
+            "last" => {
+                self.last = Some(
+                    value
+                        .parse()
+                        .with_context(|| format!("invalid count {value:?}"))?,
+                );
+            }
+            "since" => {
+                self.since = Some(
+                    DateTime::parse_from_rfc3339(value)
+                        .with_context(|| format!("invalid timestamp {value:?}"))?
+                        .to_utc(),
+                );
+            }
+            "grep" => self.grep = Some(value.to_string()),
+            "format" => self.format = value.parse()?,
+            _ => bail!("unknown key {key:?}"),
+        }
+        Ok(())
+    }
+
+    /// Does the `entry` from `sender` meet the conditions (apart from `last`)?
+    fn matches(&self, sender: &str, entry: &Entry) -> bool {
+        self.sender.as_deref().is_none_or(|name| name == sender)
+            && self.since.is_none_or(|since| entry.at >= since)
+            && self
+                .grep
+                .as_deref()
+                .is_none_or(|text| entry.content.contains(text))
+    }
+}
+
+/// Format of a report.
+///
+/// To support another format, add a variant, give it a name in [`ReportFormat::from_str`] and render it in [`ReportFormat::render`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ReportFormat {
+    /// Pretty JSON object mapping each sender to its messages.
+    #[default]
+    Json,
+    /// One JSON object per message, with `sender`, `at` and `content`.
+    Jsonl,
+    /// CSV with a `sender,at,content` header and one row per message.
+    Csv,
+    /// YAML mapping each sender to its messages.
+    Yaml,
+    /// One `<at> <sender>: <content>` line per message.
+    Text,
+}
+
+impl FromStr for ReportFormat {
+    type Err = anyhow::Error;
+
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        Ok(match name {
+            "json" => Self::Json,
+            "jsonl" => Self::Jsonl,
+            "csv" => Self::Csv,
+            "yaml" => Self::Yaml,
+            "text" => Self::Text,
+            _ => bail!("unknown format {name:?}, expected json, jsonl, csv, yaml or text"),
+        })
+    }
+}
+
+/// A message in a line based report.
+#[derive(Serialize)]
+struct Row<'a> {
+    sender: &'a str,
+    at: String,
+    content: &'a str,
+}
+
+impl<'a> Row<'a> {
+    /// The message `content` of `sender`, received `at`, without its line ending.
+    fn new(sender: &'a str, at: DateTime<Utc>, content: &'a str) -> Self {
+        Self {
+            sender,
+            at: at.to_rfc3339_opts(SecondsFormat::AutoSi, true),
+            content: content.trim_end_matches(['\r', '\n']),
+        }
+    }
+}
+
+impl Display for Row<'_> {
+    /// The row as a line of a [`ReportFormat::Text`] report.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "{} {}: {}", self.at, self.sender, self.content)
+    }
+}
+
+impl ReportFormat {
+    /// Render the `rows` of a report, each a message and its sender.
+    /// Line based formats leave out the line ending of each message, the others keep the messages as received.
+    ///
+    /// # Errors
+    /// Returns an error if serialization fails.
+    fn render(self, rows: &[(&str, &Entry)]) -> anyhow::Result<String> {
+        let by_sender = || {
+            let mut map: BTreeMap<&str, Vec<&str>> = BTreeMap::new();
+            for (sender, entry) in rows {
+                map.entry(sender).or_default().push(&entry.content);
+            }
+            map
+        };
+        let lines = || {
+            rows.iter()
+                .map(|(sender, entry)| Row::new(sender, entry.at, &entry.content))
+        };
+        Ok(match self {
+            Self::Json => format!(
+                "{}\n",
+                serde_json::to_string_pretty(&by_sender()).context("Unable to serialize report")?
+            ),
+            Self::Jsonl => lines()
+                .map(|row| Ok(format!("{}\n", serde_json::to_string(&row)?)))
+                .collect::<serde_json::Result<String>>()
+                .context("Unable to serialize report")?,
+            Self::Csv => {
+                // The header is written explicitly, so empty reports have one, too.
+                let mut writer = csv::WriterBuilder::new()
+                    .has_headers(false)
+                    .from_writer(Vec::new());
+                writer
+                    .write_record(["sender", "at", "content"])
+                    .context("Unable to serialize report")?;
+                for row in lines() {
+                    writer
+                        .serialize(row)
+                        .context("Unable to serialize report")?;
+                }
+                String::from_utf8(writer.into_inner().context("Unable to serialize report")?)
+                    .context("Report is not UTF-8")?
+            }
+            Self::Yaml => {
+                serde_yaml::to_string(&by_sender()).context("Unable to serialize report")?
+            }
+            Self::Text => lines().map(|row| row.to_string()).collect(),
+        })
+    }
+}
+
+/// A collected message.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+struct Entry {
+    /// Position among all collected messages.
+    seq: u64,
+    /// When the message was received.
+    at: DateTime<Utc>,
+    /// Message content.
+    content: String,
+}
+
+/// Receive messages on reader. If the first lines are `"hello <name>"`, claim the name (see [`validate_name()`])
+/// and use it as the sender instead of `peer`, or tell the client why it can not have it, and let it try again.
+/// When receiving `"report"` (optionally followed by a [`Query`]),
 /// send a report request, await the reply, and forward it on `writer`.
-/// Else, just forward the message on the collection sender `tx`.
+/// If the query is invalid, tell the client why instead.
+/// When receiving `"snapshot"`, request a snapshot (see [`collect_with()`]) and forward the outcome on `writer`.
+/// When receiving `"retention"`, request the retention state and forward it on `writer`.
+/// When receiving `"stats"`, request the [`SenderStats`] and forward them on `writer`, one line per sender.
+/// When receiving `"search"` followed by terms, request the best matches and forward them on `writer`.
+/// When receiving `"follow"` (optionally followed by a sender), forward every message collected from then on
+/// (only those of the sender, if given) on `writer` as it arrives, until receiving `"unfollow"`.
+/// Else, just forward the message on the collection sender `tx`, with the claimed name or `peer` as the sender.
 ///
 /// # Termination
-/// In case the `reader` has no more bytes (`read_line` returned `Ok(0)`), terminate the future.
-pub async fn handle_connection<Reader, Writer>(
-    name: String,
+/// In case the `reader` has no more bytes (`read_until` returned `Ok(0)`), terminate the future.
+///
+/// # Errors
+/// Returns an error if reading a line fails, a line is not valid UTF-8 or longer than [`MAX_LINE`](crate::MAX_LINE) bytes,
+/// or the collector is gone.
+#[tracing::instrument(name = "connection", skip_all, fields(client = %peer))]
+pub async fn handle_connection<Reader, Writer, Peer>(
+    peer: Peer,
     reader: Reader,
     mut writer: Writer,
     tx: mpsc::Sender<Message>,
@@ -38,79 +359,824 @@ pub async fn handle_connection<Reader, Writer>(
 where
     Reader: AsyncRead + Unpin,
     Writer: AsyncWrite + Unpin,
+    Peer: Display,
 {
-    let mut line = String::new();
+    let mut name = peer.to_string();
+    let mut line = Vec::new();
     let mut reader = BufReader::new(reader);
+    let mut following: Option<Following> = None;
+    // Held until the connection ends.
+    let mut _claim: Option<Claim> = None;
+    let mut greeting = true;
 
     loop {
-        if let Ok(bytes_read) = reader.read_line(&mut line).await {
-            if bytes_read == 0 {
-                break Ok(());
+        // `read_bounded_line` is cancel safe, unlike `read_line`: a partial line stays in `line` while following.
+        let bytes_read = tokio::select! {
+            result = read_bounded_line(&mut reader, &mut line) => result.context("Failed to read line")?,
+            collected = async {
+                match &mut following {
+                    Some(following) => following.next().await,
+                    None => std::future::pending().await,
+                }
+            } => {
+                let output = match collected {
+                    Ok(collected) => collected.to_string(),
+                    Err(RecvError::Lagged(missed)) => format!("Missed {missed} message(s)\n"),
+                    Err(RecvError::Closed) => {
+                        following = None;
+                        "Collector stopped, no longer following\n".to_string()
+                    }
+                };
+                writer
+                    .write_all(output.as_bytes())
+                    .await
+                    .context("Failed to forward collected message to client")?;
+                continue;
             }
-            if is_report(&line) {
+        };
+        if bytes_read == 0 {
+            tracing::info!("Client disconnected");
+            break Ok(());
+        }
+        let text = std::str::from_utf8(&line).context("Received invalid UTF-8")?;
+        let hello = greeting.then(|| hello_request(text)).flatten();
+        greeting = hello.is_some();
+        match report_query(text) {
+            Some(Ok(query)) => {
+                tracing::debug!(?query, "Client requested report");
                 let (sender, receiver) = oneshot::channel();
-                let request = Message::Report { reply: sender };
+                let request = Message::Report {
+                    query,
+                    reply: sender,
+                };
                 tx.send(request)
                     .await
                     .context("Failed to broadcast message from client")?;
-                let report = receiver.await.context("Failed to fetch report")?;
+                let report = match receiver.await.context("Failed to fetch report")? {
+                    Ok(report) => report,
+                    Err(error) => format!("Failed to render report: {error}\n"),
+                };
                 writer
                     .write_all(report.as_bytes())
                     .await
                     .context("Failed to forward report to client")?;
-            } else {
-                tx.send(Message::Text {
-                    sender: name.clone(),
-                    content: line.clone(),
-                })
-                .await
-                .context("Failed to send text message to server")?;
             }
-            line.clear();
+            Some(Err(error)) => {
+                writer
+                    .write_all(format!("Invalid query: {error:#}\n").as_bytes())
+                    .await
+                    .context("Failed to reject query")?;
+            }
+            None if is_snapshot(text) => {
+                tracing::debug!("Client requested snapshot");
+                let (sender, receiver) = oneshot::channel();
+                tx.send(Message::Snapshot { reply: sender })
+                    .await
+                    .context("Failed to request snapshot")?;
+                let outcome = receiver.await.context("Failed to fetch snapshot outcome")?;
+                writer
+                    .write_all(outcome.as_bytes())
+                    .await
+                    .context("Failed to forward snapshot outcome to client")?;
+            }
+            None if is_retention(text) => {
+                let (sender, receiver) = oneshot::channel();
+                tx.send(Message::Retention { reply: sender })
+                    .await
+                    .context("Failed to request retention state")?;
+                let state = receiver.await.context("Failed to fetch retention state")?;
+                writer
+                    .write_all(state.as_bytes())
+                    .await
+                    .context("Failed to forward retention state to client")?;
+            }
+            None if is_stats(text) => {
+                let (sender, receiver) = oneshot::channel();
+                tx.send(Message::Stats { reply: sender })
+                    .await
+                    .context("Failed to request stats")?;
+                let stats = receiver.await.context("Failed to fetch stats")?;
+                writer
+                    .write_all(format_stats(&stats).as_bytes())
+                    .await
+                    .context("Failed to forward stats to client")?;
+            }
+            None => {
+                if let Some(requested) = hello {
+                    let answer = match validate_name(requested) {
+                        Ok(()) => {
+                            let (sender, receiver) = oneshot::channel();
+                            tx.send(Message::Hello {
+                                name: requested.to_string(),
+                                reply: sender,
+                            })
+                            .await
+                            .context("Failed to claim name")?;
+                            match receiver.await.context("Failed to fetch claim")? {
+                                Some(claimed) => {
+                                    tracing::info!(name = requested, "Client claimed name");
+                                    name = requested.to_string();
+                                    _claim = Some(claimed);
+                                    greeting = false;
+                                    format!("Hello, {name}\n")
+                                }
+                                None => format!("Name {requested} is taken\n"),
+                            }
+                        }
+                        Err(error) => format!("Invalid name: {error:#}\n"),
+                    };
+                    writer
+                        .write_all(answer.as_bytes())
+                        .await
+                        .context("Failed to answer hello")?;
+                } else if let Some(terms) = search_terms(text) {
+                    let (sender, receiver) = oneshot::channel();
+                    tx.send(Message::Search {
+                        terms: terms.to_string(),
+                        reply: sender,
+                    })
+                    .await
+                    .context("Failed to request search")?;
+                    let matches = receiver.await.context("Failed to fetch matches")?;
+                    writer
+                        .write_all(format_matches(&matches).as_bytes())
+                        .await
+                        .context("Failed to forward matches to client")?;
+                } else if let Some(followed) = follow_request(text) {
+                    tracing::debug!(?followed, "Client follows");
+                    let (sender, receiver) = oneshot::channel();
+                    tx.send(Message::Follow { reply: sender })
+                        .await
+                        .context("Failed to request following")?;
+                    let rx = receiver.await.context("Failed to follow")?;
+                    let answer = match &followed {
+                        Some(sender) => format!("Following {sender}\n"),
+                        None => "Following all senders\n".to_string(),
+                    };
+                    following = Some(Following {
+                        rx,
+                        sender: followed,
+                    });
+                    writer
+                        .write_all(answer.as_bytes())
+                        .await
+                        .context("Failed to confirm following")?;
+                } else if is_unfollow(text) {
+                    let answer = match following.take() {
+                        Some(_) => "Unfollowed\n",
+                        None => "Not following\n",
+                    };
+                    writer
+                        .write_all(answer.as_bytes())
+                        .await
+                        .context("Failed to confirm unfollowing")?;
+                } else {
+                    tx.send(Message::Text {
+                        sender: name.clone(),
+                        content: text.to_string(),
+                    })
+                    .await
+                    .context("Failed to send text message to server")?;
+                }
+            }
         }
+        line.clear();
     }
 }
 
-/// If `line` is `"report"` or `"report\n"` or `"report\r\n"`, return `true`.
-fn is_report(line: &str) -> bool {
-    line == "report" || line == "report\n" || line == "report\r\n"
+/// A connection's subscription to newly collected messages.
+#[derive(Debug)]
+struct Following {
+    /// Every collected message.
+    rx: broadcast::Receiver<Collected>,
+    /// Only forward messages of this sender.
+    sender: Option<String>,
+}
+
+impl Following {
+    /// The next collected message of the followed sender.
+    ///
+    /// # Cancel safety
+    /// This method is cancel safe, like [`broadcast::Receiver::recv`].
+    async fn next(&mut self) -> Result<Collected, RecvError> {
+        loop {
+            let collected = self.rx.recv().await?;
+            if self
+                .sender
+                .as_deref()
+                .is_none_or(|sender| sender == collected.sender)
+            {
+                return Ok(collected);
+            }
+        }
+    }
+}
+
+/// If `line` is `"hello"`, followed by a space and a name, return the name.
+fn hello_request(line: &str) -> Option<&str> {
+    line.trim_end_matches(['\r', '\n'])
+        .strip_prefix("hello ")
+        .map(str::trim)
+}
+
+/// Check that `name` can be claimed: up to [`MAX_NAME_LENGTH`] letters, digits, `-`, `_` or `.`.
+/// Without `:`, names never clash with peers identified by address.
+///
+/// # Errors
+/// Returns an error explaining what is wrong with the name.
+pub fn validate_name(name: &str) -> anyhow::Result<()> {
+    if name.is_empty() {
+        bail!("name must not be empty");
+    }
+    if name.len() > MAX_NAME_LENGTH {
+        bail!("name must be at most {MAX_NAME_LENGTH} bytes");
+    }
+    if let Some(c) = name
+        .chars()
+        .find(|c| !c.is_alphanumeric() && !matches!(c, '-' | '_' | '.'))
+    {
+        bail!("unexpected {c:?}, use letters, digits, '-', '_' or '.'");
+    }
+    Ok(())
+}
+
+/// If `line` is `"search"`, optionally followed by a space and search terms, return the terms.
+fn search_terms(line: &str) -> Option<&str> {
+    let terms = line.trim_end_matches(['\r', '\n']).strip_prefix("search")?;
+    if !terms.is_empty() && !terms.starts_with(' ') {
+        return None;
+    }
+    Some(terms.trim())
+}
+
+/// A `Found <n> match(es)` line, followed by one line per match.
+fn format_matches(matches: &[Match]) -> String {
+    let mut text = format!("Found {} match(es)\n", matches.len());
+    for found in matches {
+        text.push_str(&format!("{found}\n"));
+    }
+    text
+}
+
+/// If `line` is `"follow"`, optionally followed by a space and a sender, return the sender to follow, if any.
+fn follow_request(line: &str) -> Option<Option<String>> {
+    let sender = line.trim_end_matches(['\r', '\n']).strip_prefix("follow")?;
+    if !sender.is_empty() && !sender.starts_with(' ') {
+        return None;
+    }
+    let sender = sender.trim();
+    Some((!sender.is_empty()).then(|| sender.to_string()))
+}
+
+/// If `line` is `"unfollow"` or `"unfollow\n"` or `"unfollow\r\n"`, return `true`.
+fn is_unfollow(line: &str) -> bool {
+    line == "unfollow" || line == "unfollow\n" || line == "unfollow\r\n"
+}
+
+/// If `line` is `"report"`, optionally followed by a space and the arguments of a [`Query`], parse the query.
+pub fn report_query(line: &str) -> Option<anyhow::Result<Query>> {
+    let arguments = line.trim_end_matches(['\r', '\n']).strip_prefix("report")?;
+    if !arguments.is_empty() && !arguments.starts_with(' ') {
+        return None;
+    }
+    Some(arguments.parse())
+}
+
+/// If `line` is `"snapshot"` or `"snapshot\n"` or `"snapshot\r\n"`, return `true`.
+fn is_snapshot(line: &str) -> bool {
+    line == "snapshot" || line == "snapshot\n" || line == "snapshot\r\n"
+}
+
+/// If `line` is `"retention"` or `"retention\n"` or `"retention\r\n"`, return `true`.
+fn is_retention(line: &str) -> bool {
+    line == "retention" || line == "retention\n" || line == "retention\r\n"
+}
+
+/// If `line` is `"stats"` or `"stats\n"` or `"stats\r\n"`, return `true`.
+fn is_stats(line: &str) -> bool {
+    line == "stats" || line == "stats\n" || line == "stats\r\n"
+}
+
+/// One `Stats for <sender>: ...` line per sender, or a single line if there are none.
+fn format_stats(stats: &BTreeMap<String, SenderStats>) -> String {
+    if stats.is_empty() {
+        return "Stats: no senders yet\n".to_string();
+    }
+    stats
+        .iter()
+        .map(|(sender, stats)| format!("Stats for {sender}: {stats}\n"))
+        .collect()
+}
+
+/// Everything a sender sent so far, including messages evicted since.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SenderStats {
+    /// Number of messages.
+    pub messages: u64,
+    /// Bytes of all message contents.
+    pub bytes: u64,
+    /// When the first message was received.
+    pub first_seen: DateTime<Utc>,
+    /// When the last message was received.
+    pub last_seen: DateTime<Utc>,
+}
+
+impl SenderStats {
+    /// Stats of a single message of `bytes`, received `at`.
+    fn new(bytes: usize, at: DateTime<Utc>) -> Self {
+        Self {
+            messages: 1,
+            bytes: bytes as u64,
+            first_seen: at,
+            last_seen: at,
+        }
+    }
+
+    /// Account for another message of `bytes`, received `at`.
+    fn record(&mut self, bytes: usize, at: DateTime<Utc>) {
+        self.messages += 1;
+        self.bytes += bytes as u64;
+        self.first_seen = self.first_seen.min(at);
+        self.last_seen = self.last_seen.max(at);
+    }
+
+    /// Average bytes per message.
+    pub fn average_length(&self) -> f64 {
+        self.bytes as f64 / self.messages as f64
+    }
+}
+
+impl Display for SenderStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} message(s) of {} byte(s) ({:.1} on average), first seen {}, last seen {}",
+            self.messages,
+            self.bytes,
+            self.average_length(),
+            self.first_seen.to_rfc3339_opts(SecondsFormat::Secs, true),
+            self.last_seen.to_rfc3339_opts(SecondsFormat::Secs, true),
+        )
+    }
+}
+
+/// Limits on the collected messages, enforced by evicting the oldest ones. No limit if `None`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Retention {
+    /// Evict messages older than this.
+    pub max_age: Option<Duration>,
+    /// Keep at most this many messages of each sender.
+    pub max_per_sender: Option<usize>,
+    /// Keep at most this many message bytes over all senders.
+    /// The search index and the per-sender bookkeeping are not counted.
+    pub max_bytes: Option<usize>,
+}
+
+/// How many messages were evicted, by reason.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Evictions {
+    /// Messages older than [`Retention::max_age`].
+    pub age: u64,
+    /// Messages beyond [`Retention::max_per_sender`].
+    pub per_sender: u64,
+    /// Messages beyond [`Retention::max_bytes`].
+    pub memory: u64,
+}
+
+impl Display for Evictions {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "evicted {} by age, {} by sender limit, {} by memory limit",
+            self.age, self.per_sender, self.memory
+        )
+    }
+}
+
+/// The collected messages, as kept in memory and in snapshots.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Collection {
+    /// Position of the next collected message.
+    next: u64,
+    /// Messages by sender, oldest first.
+    messages: HashMap<String, VecDeque<Entry>>,
+    /// Messages evicted so far.
+    #[serde(default)]
+    evictions: Evictions,
+    /// Stats of every sender so far, kept when their messages are evicted.
+    #[serde(default)]
+    senders: HashMap<String, SenderStats>,
+    /// Bytes of all message contents.
+    #[serde(skip)]
+    bytes: usize,
+    /// Full-text index of all messages.
+    #[serde(skip)]
+    index: search::Index,
+}
+
+impl Collection {
+    /// Restore the collection from the snapshot at `path`, or start empty if there is none.
+    ///
+    /// # Errors
+    /// Returns an error if the snapshot exists, but can not be read or parsed.
+    pub fn load(path: impl AsRef<Path>) -> anyhow::Result<Self> {
+        let path = path.as_ref();
+        let content = match std::fs::read_to_string(path) {
+            Ok(content) => content,
+            Err(error) if error.kind() == ErrorKind::NotFound => return Ok(Self::default()),
+            Err(error) => {
+                return Err(error)
+                    .with_context(|| format!("Failed to read snapshot {}", path.display()))
+            }
+        };
+        let mut collection: Self = serde_json::from_str(&content)
+            .with_context(|| format!("Failed to parse snapshot {}", path.display()))?;
+        collection.bytes = collection
+            .messages
+            .values()
+            .flatten()
+            .map(|entry| entry.content.len())
+            .sum();
+        for (sender, entries) in &collection.messages {
+            for entry in entries {
+                collection.index.insert(sender, entry);
+            }
+        }
+        // Snapshots from before stats were kept only tell about the messages still there.
+        for (sender, entries) in &collection.messages {
+            if collection.senders.contains_key(sender) {
+                continue;
+            }
+            let mut entries = entries.iter();
+            let Some(first) = entries.next() else {
+                continue;
+            };
+            let mut stats = SenderStats::new(first.content.len(), first.at);
+            for entry in entries {
+                stats.record(entry.content.len(), entry.at);
+            }
+            collection.senders.insert(sender.clone(), stats);
+        }
+        Ok(collection)
+    }
+
+    /// Write a snapshot to `path`. The snapshot is written to a temporary file next to it first,
+    /// then renamed, so `path` always holds a complete snapshot.
+    ///
+    /// # Errors
+    /// Returns an error if serializing, writing or renaming fails.
+    pub async fn save(&self, path: impl AsRef<Path>) -> anyhow::Result<()> {
+        let path = path.as_ref();
+        let content = serde_json::to_string(self).context("Unable to serialize state")?;
+        let mut temporary = path.as_os_str().to_owned();
+        temporary.push(".tmp");
+        let temporary = PathBuf::from(temporary);
+
+        let mut file = tokio::fs::File::create(&temporary)
+            .await
+            .with_context(|| format!("Failed to create {}", temporary.display()))?;
+        file.write_all(content.as_bytes())
+            .await
+            .with_context(|| format!("Failed to write {}", temporary.display()))?;
+        file.sync_all()
+            .await
+            .with_context(|| format!("Failed to sync {}", temporary.display()))?;
+        tokio::fs::rename(&temporary, path)
+            .await
+            .with_context(|| format!("Failed to rename snapshot to {}", path.display()))
+    }
+
+    /// Number of collected messages.
+    pub fn len(&self) -> usize {
+        self.messages.values().map(VecDeque::len).sum()
+    }
+
+    /// Are there no collected messages?
+    pub fn is_empty(&self) -> bool {
+        self.messages.values().all(VecDeque::is_empty)
+    }
+
+    /// Bytes of all message contents.
+    pub fn bytes(&self) -> usize {
+        self.bytes
+    }
+
+    /// Messages evicted so far.
+    pub fn evictions(&self) -> Evictions {
+        self.evictions
+    }
+
+    /// Stats of every sender so far.
+    pub fn stats(&self) -> BTreeMap<String, SenderStats> {
+        self.senders
+            .iter()
+            .map(|(sender, stats)| (sender.clone(), *stats))
+            .collect()
+    }
+
+    /// The at most `limit` messages best matching the `terms`, see [`Match`].
+    pub fn search(&self, terms: &str, limit: usize) -> Vec<Match> {
+        self.index.search(terms, &self.messages, limit)
+    }
+
+    /// Add a message of `sender`, received `at`, then evict messages beyond the limits of `retention`.
+    fn push(&mut self, sender: String, content: String, at: DateTime<Utc>, retention: &Retention) {
+        self.bytes += content.len();
+        match self.senders.get_mut(&sender) {
+            Some(stats) => stats.record(content.len(), at),
+            None => {
+                self.senders
+                    .insert(sender.clone(), SenderStats::new(content.len(), at));
+            }
+        }
+        let entry = Entry {
+            seq: self.next,
+            at,
+            content,
+        };
+        self.index.insert(&sender, &entry);
+        let entries = self.messages.entry(sender).or_default();
+        entries.push_back(entry);
+        self.next += 1;
+
+        if let Some(max) = retention.max_per_sender {
+            while entries.len() > max {
+                let evicted = entries.pop_front().expect("More entries than the limit");
+                self.bytes -= evicted.content.len();
+                self.index.remove(&evicted);
+                self.evictions.per_sender += 1;
+            }
+        }
+        self.enforce_memory(retention);
+    }
+
+    /// Evict all messages beyond the limits of `retention`, as of `now`.
+    fn enforce(&mut self, retention: &Retention, now: DateTime<Utc>) {
+        if let Some(max_age) = retention.max_age {
+            let oldest = now - max_age;
+            for entries in self.messages.values_mut() {
+                while entries.front().is_some_and(|entry| entry.at < oldest) {
+                    let evicted = entries.pop_front().expect("Front entry exists");
+                    self.bytes -= evicted.content.len();
+                    self.index.remove(&evicted);
+                    self.evictions.age += 1;
+                }
+            }
+        }
+        if let Some(max) = retention.max_per_sender {
+            for entries in self.messages.values_mut() {
+                while entries.len() > max {
+                    let evicted = entries.pop_front().expect("More entries than the limit");
+                    self.bytes -= evicted.content.len();
+                    self.index.remove(&evicted);
+                    self.evictions.per_sender += 1;
+                }
+            }
+        }
+        self.enforce_memory(retention);
+    }
+
+    /// Evict the oldest messages until the contents fit into [`Retention::max_bytes`], then drop senders without messages.
+    fn enforce_memory(&mut self, retention: &Retention) {
+        if let Some(max) = retention.max_bytes {
+            while self.bytes > max && self.evict_oldest() {
+                self.evictions.memory += 1;
+            }
+        }
+        self.messages.retain(|_, entries| !entries.is_empty());
+    }
+
+    /// Evict the oldest message over all senders, if there is one.
+    fn evict_oldest(&mut self) -> bool {
+        let oldest = self
+            .messages
+            .values_mut()
+            .filter(|entries| !entries.is_empty())
+            .min_by_key(|entries| entries.front().map(|entry| entry.seq));
+        match oldest.and_then(VecDeque::pop_front) {
+            Some(evicted) => {
+                self.bytes -= evicted.content.len();
+                self.index.remove(&evicted);
+                true
+            }
+            None => false,
+        }
+    }
+}
+
+/// Where and how often [`collect_with()`] snapshots the collected messages.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Snapshots {
+    /// Snapshot file.
+    pub path: PathBuf,
+    /// Period between snapshots, taken only if messages were collected since the last one.
+    pub interval: Duration,
 }
 
 /// Receive messages on the given [`mpsc::Receiver`].
-/// On receiving a simple text message, just add it to the hashmap (the key being the source socket address).
-/// On receiving a report message including a reply callback ([`oneshot::Sender`]), serialize the hashmap, then send it on the callback.
+/// On receiving a simple text message, just add it to the hashmap (the key being the sender).
+/// On receiving a report message including a [`Query`] and a reply callback ([`oneshot::Sender`]),
+/// render the matching part of the hashmap in the requested [`ReportFormat`], then send it (or why rendering failed) on the callback.
 ///
 /// # Termination
 /// In case there are no more senders, terminate the future.
 ///
 /// # Errors
-/// Collection can fail if state serialization fails.
-pub async fn collect(mut rx: mpsc::Receiver<Message>) -> anyhow::Result<()> {
-    let mut map: HashMap<String, Vec<String>> = HashMap::new();
+/// Collection does not fail without snapshots.
+pub async fn collect(rx: mpsc::Receiver<Message>) -> anyhow::Result<()> {
+    collect_with(rx, Collection::default(), None, Retention::default()).await
+}
+
+/// Like [`collect()`], but start from `collection`, and if `snapshots` are given,
+/// periodically write a snapshot, as well as on request and when terminating.
+/// Messages beyond the limits of `retention` are evicted as new ones arrive, and expired ones every second.
+/// On receiving a retention request, send what is kept and the [`Evictions`] so far,
+/// on receiving a senders request, send how many messages of each sender are kept,
+/// on receiving a stats request, send the [`SenderStats`] of each sender,
+/// on receiving a search request, send the best matches among the kept messages,
+/// on receiving a follow request, send a receiver of every message [`Collected`] from then on,
+/// on receiving a hello request, send a [`Claim`] on the name unless a live connection holds it,
+/// and on receiving a claimed request, send whether a live connection holds the name.
+///
+/// # Termination
+/// In case there are no more senders, write a final snapshot and terminate the future.
+///
+/// # Errors
+/// Collection can fail if the final snapshot fails.
+pub async fn collect_with(
+    mut rx: mpsc::Receiver<Message>,
+    mut collection: Collection,
+    snapshots: Option<Snapshots>,
+    retention: Retention,
+) -> anyhow::Result<()> {
+    // Without snapshots, the interval branch is disabled, so its period does not matter.
+    let period = snapshots
+        .as_ref()
+        .map_or(Duration::from_secs(60), |snapshots| snapshots.interval);
+    let mut interval = tokio::time::interval_at(Instant::now() + period, period);
+    interval.set_missed_tick_behavior(MissedTickBehavior::Delay);
+    let mut expiry = tokio::time::interval(Duration::from_secs(1));
+    expiry.set_missed_tick_behavior(MissedTickBehavior::Delay);
+    collection.enforce(&retention, Utc::now());
+    let mut dirty = false;
+    let (followers, _) = broadcast::channel(FOLLOW_CAPACITY);
+    // The claimed names, each released once its connection drops the receiver.
+    let mut names: HashMap<String, oneshot::Sender<()>> = HashMap::new();
+
     loop {
-        match rx.recv().await {
-            Some(message) => match message {
-                Message::Text { sender, content } => {
-                    map.entry(sender).or_default().push(content);
-                }
-                Message::Report { reply } => {
-                    if reply
-                        .send(format!(
-                            "{}\n",
-                            serde_json::to_string_pretty(&map)
-                                .context("Unable to serialize state")?,
-                        ))
-                        .is_err()
-                    {
-                        eprintln!("Failed to send report on client callback");
+        tokio::select! {
+            message = rx.recv() => match message {
+                Some(Message::Text { sender, content }) => {
+                    let at = Utc::now();
+                    if followers.receiver_count() > 0 {
+                        // Followers may unsubscribe in the meantime, then nobody is left to tell.
+                        let _ = followers.send(Collected {
+                            sender: sender.clone(),
+                            at,
+                            content: content.clone(),
+                        });
+                    }
+                    collection.push(sender, content, at, &retention);
+                    dirty = true;
+                }
+                Some(Message::Report { query, reply }) => {
+                    collection.enforce(&retention, Utc::now());
+                    let rows = report(&collection.messages, &query);
+                    let rendered = query.format.render(&rows).map_err(|error| {
+                        tracing::warn!(?error, "Failed to render report");
+                        format!("{error:#}")
+                    });
+                    if reply.send(rendered).is_err() {
+                        tracing::warn!("Failed to send report on client callback");
+                    }
+                }
+                Some(Message::Snapshot { reply }) => {
+                    let outcome = match &snapshots {
+                        Some(snapshots) => match collection.save(&snapshots.path).await {
+                            Ok(()) => {
+                                dirty = false;
+                                format!("Snapshot of {} message(s) saved\n", collection.len())
+                            }
+                            Err(error) => {
+                                tracing::warn!(?error, "Failed to snapshot");
+                                "Snapshot failed\n".to_string()
+                            }
+                        },
+                        None => "Snapshots are not configured\n".to_string(),
+                    };
+                    if reply.send(outcome).is_err() {
+                        tracing::warn!("Failed to send snapshot outcome on client callback");
+                    }
+                }
+                Some(Message::Retention { reply }) => {
+                    collection.enforce(&retention, Utc::now());
+                    let state = format!(
+                        "Retention: {} message(s) of {} byte(s) kept, {}\n",
+                        collection.len(),
+                        collection.bytes(),
+                        collection.evictions()
+                    );
+                    if reply.send(state).is_err() {
+                        tracing::warn!("Failed to send retention state on client callback");
+                    }
+                }
+                Some(Message::Senders { reply }) => {
+                    collection.enforce(&retention, Utc::now());
+                    let senders = collection
+                        .messages
+                        .iter()
+                        .map(|(sender, entries)| (sender.clone(), entries.len()))
+                        .collect();
+                    if reply.send(senders).is_err() {
+                        tracing::warn!("Failed to send senders on client callback");
+                    }
+                }
+                Some(Message::Stats { reply }) => {
+                    if reply.send(collection.stats()).is_err() {
+                        tracing::warn!("Failed to send stats on client callback");
                     }
                 }
+                Some(Message::Search { terms, reply }) => {
+                    collection.enforce(&retention, Utc::now());
+                    if reply.send(collection.search(&terms, SEARCH_LIMIT)).is_err() {
+                        tracing::warn!("Failed to send matches on client callback");
+                    }
+                }
+                Some(Message::Hello { name, reply }) => {
+                    names.retain(|_, held| !held.is_closed());
+                    let claim = match names.entry(name) {
+                        hash_map::Entry::Occupied(_) => None,
+                        hash_map::Entry::Vacant(vacant) => {
+                            let (held, _held) = oneshot::channel();
+                            vacant.insert(held);
+                            Some(Claim { _held })
+                        }
+                    };
+                    if reply.send(claim).is_err() {
+                        tracing::warn!("Failed to send claim on client callback");
+                    }
+                }
+                Some(Message::Claimed { name, reply }) => {
+                    let claimed = names.get(&name).is_some_and(|held| !held.is_closed());
+                    if reply.send(claimed).is_err() {
+                        tracing::warn!("Failed to send claimed state on client callback");
+                    }
+                }
+                Some(Message::Follow { reply }) => {
+                    if reply.send(followers.subscribe()).is_err() {
+                        tracing::warn!("Failed to send followed messages on client callback");
+                    }
+                }
+                None => break,
             },
-            None => {
-                break Ok(());
+            _ = expiry.tick(), if retention.max_age.is_some() => {
+                let before = collection.evictions();
+                collection.enforce(&retention, Utc::now());
+                let evicted = collection.evictions().age - before.age;
+                if evicted > 0 {
+                    tracing::debug!(evicted, evictions = %collection.evictions(), "Evicted expired messages");
+                    dirty = true;
+                }
+            }
+            _ = interval.tick(), if dirty && snapshots.is_some() => {
+                let snapshots = snapshots.as_ref().expect("Only ticking with snapshots");
+                match collection.save(&snapshots.path).await {
+                    Ok(()) => {
+                        tracing::debug!(messages = collection.len(), "Saved snapshot");
+                        dirty = false;
+                    }
+                    Err(error) => tracing::warn!(?error, "Failed to snapshot"),
+                }
             }
         }
     }
+
+    if let Some(snapshots) = &snapshots {
+        collection
+            .save(&snapshots.path)
+            .await
+            .context("Failed to write final snapshot")?;
+        tracing::info!(messages = collection.len(), "Saved final snapshot");
+    }
+    Ok(())
+}
+
+/// The messages in `map` matching `query` with their senders, ordered by sender, then by arrival.
+fn report<'a>(
+    map: &'a HashMap<String, VecDeque<Entry>>,
+    query: &Query,
+) -> Vec<(&'a str, &'a Entry)> {
+    let mut matches = map
+        .iter()
+        .flat_map(|(sender, entries)| entries.iter().map(move |entry| (sender.as_str(), entry)))
+        .filter(|(sender, entry)| query.matches(sender, entry))
+        .collect::<Vec<_>>();
+    if let Some(last) = query.last {
+        matches.sort_unstable_by_key(|(_, entry)| entry.seq);
+        matches.drain(..matches.len().saturating_sub(last));
+    }
+    matches.sort_unstable_by_key(|(sender, entry)| (*sender, entry.seq));
+    matches
 }
 
 #[cfg(test)]
@@ -136,4 +1202,541 @@ mod test {
 
         tokio::join!(client, server).0.unwrap().unwrap();
     }
+
+    #[tokio::test]
+    async fn fails_on_invalid_utf8() {
+        let writer = Mock::new().build();
+        let reader = Mock::new().read(b"\xff\n").build();
+
+        let (tx, _rx) = mpsc::channel(16);
+
+        let result = handle_connection("test".to_string(), reader, writer, tx).await;
+        assert!(result.is_err());
+    }
+
+    #[tokio::test]
+    async fn drops_client_sending_overlong_line() {
+        let writer = Mock::new().build();
+        let reader = Mock::new().read(&[b'x'; crate::MAX_LINE]).build();
+
+        let (tx, mut rx) = mpsc::channel(16);
+
+        let result = handle_connection("test".to_string(), reader, writer, tx).await;
+        let error = format!("{:#}", result.unwrap_err());
+        assert!(error.contains("Line longer than 65536 bytes"), "{error}");
+        assert!(rx.try_recv().is_err());
+    }
+
+    #[test]
+    fn parses_queries() {
+        assert_eq!(
+            report_query("report\r\n").unwrap().unwrap(),
+            Query::default()
+        );
+        assert_eq!(
+            report_query("report sender=alice last=2 since=2024-01-01T12:00:00+01:00 grep=hi\n")
+                .unwrap()
+                .unwrap(),
+            Query {
+                sender: Some("alice".to_string()),
+                last: Some(2),
+                since: Some("2024-01-01T11:00:00Z".parse().unwrap()),
+                grep: Some("hi".to_string()),
+                format: ReportFormat::Json,
+            }
+        );
+        assert_eq!(
+            report_query("report format=csv").unwrap().unwrap().format,
+            ReportFormat::Csv
+        );
+        assert!(report_query("reports\n").is_none());
+        assert!(report_query("hello\n").is_none());
+    }
+
+    #[test]
+    fn rejects_invalid_queries() {
+        let error = |line| format!("{:#}", report_query(line).unwrap().unwrap_err());
+        assert_eq!(error("report last"), "expected key=value, got \"last\"");
+        assert_eq!(
+            error("report last=many"),
+            "invalid count \"many\": invalid digit found in string"
+        );
+        assert_eq!(error("report color=red"), "unknown key \"color\"");
+        assert_eq!(
+            error("report format=xml"),
+            "unknown format \"xml\", expected json, jsonl, csv, yaml or text"
+        );
+        assert!(error("report since=yesterday").starts_with("invalid timestamp \"yesterday\""));
+    }
+
+    /// Messages of alice and bob, one each minute from 12:00 on.
+    fn messages() -> HashMap<String, VecDeque<Entry>> {
+        let entry = |seq, minute, content: &str| Entry {
+            seq,
+            at: format!("2024-01-01T12:{minute:02}:00Z").parse().unwrap(),
+            content: content.to_string(),
+        };
+        HashMap::from([
+            (
+                "alice".to_string(),
+                VecDeque::from([entry(0, 0, "hi bob\n"), entry(2, 2, "how are you\n")]),
+            ),
+            (
+                "bob".to_string(),
+                VecDeque::from([entry(1, 1, "hi alice\n"), entry(3, 3, "fine, \"thanks\"\n")]),
+            ),
+        ])
+    }
+
+    #[test]
+    fn reports_matching_messages() {
+        let map = messages();
+        let report = |arguments: &str| {
+            report(&map, &arguments.parse().unwrap())
+                .into_iter()
+                .map(|(sender, entry)| (sender, entry.content.trim_end()))
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(
+            report("sender=bob"),
+            [("bob", "hi alice"), ("bob", "fine, \"thanks\"")]
+        );
+        assert_eq!(
+            report("last=3"),
+            [
+                ("alice", "how are you"),
+                ("bob", "hi alice"),
+                ("bob", "fine, \"thanks\"")
+            ]
+        );
+        assert_eq!(
+            report("since=2024-01-01T12:02:00Z"),
+            [("alice", "how are you"), ("bob", "fine, \"thanks\"")]
+        );
+        assert_eq!(report("grep=hi last=1"), [("bob", "hi alice")]);
+        assert!(report("sender=carol").is_empty());
+    }
+
+    /// The report of all [`messages()`] in `format`.
+    fn render(format: ReportFormat) -> String {
+        let map = messages();
+        format.render(&report(&map, &Query::default())).unwrap()
+    }
+
+    #[test]
+    fn renders_json() {
+        assert_eq!(
+            render(ReportFormat::Json),
+            r#"{
+  "alice": [
+    "hi bob\n",
+    "how are you\n"
+  ],
+  "bob": [
+    "hi alice\n",
+    "fine, \"thanks\"\n"
+  ]
+}
+"#
+        );
+    }
+
+    #[test]
+    fn renders_jsonl() {
+        assert_eq!(
+            render(ReportFormat::Jsonl),
+            r#"{"sender":"alice","at":"2024-01-01T12:00:00Z","content":"hi bob"}
+{"sender":"alice","at":"2024-01-01T12:02:00Z","content":"how are you"}
+{"sender":"bob","at":"2024-01-01T12:01:00Z","content":"hi alice"}
+{"sender":"bob","at":"2024-01-01T12:03:00Z","content":"fine, \"thanks\""}
+"#
+        );
+    }
+
+    #[test]
+    fn renders_csv() {
+        assert_eq!(
+            render(ReportFormat::Csv),
+            r#"sender,at,content
+alice,2024-01-01T12:00:00Z,hi bob
+alice,2024-01-01T12:02:00Z,how are you
+bob,2024-01-01T12:01:00Z,hi alice
+bob,2024-01-01T12:03:00Z,"fine, ""thanks"""
+"#
+        );
+    }
+
+    #[test]
+    fn renders_yaml() {
+        assert_eq!(
+            render(ReportFormat::Yaml),
+            r#"alice:
+- |
+  hi bob
+- |
+  how are you
+bob:
+- |
+  hi alice
+- |
+  fine, "thanks"
+"#
+        );
+    }
+
+    #[test]
+    fn renders_text() {
+        assert_eq!(
+            render(ReportFormat::Text),
+            r#"2024-01-01T12:00:00Z alice: hi bob
+2024-01-01T12:02:00Z alice: how are you
+2024-01-01T12:01:00Z bob: hi alice
+2024-01-01T12:03:00Z bob: fine, "thanks"
+"#
+        );
+    }
+
+    #[tokio::test]
+    async fn answers_invalid_query() {
+        let writer = Mock::new()
+            .write(b"Invalid query: unknown key \"color\"\n")
+            .build();
+        let reader = Mock::new().read(b"report color=red\n").build();
+
+        let (tx, _rx) = mpsc::channel(16);
+
+        handle_connection("test".to_string(), reader, writer, tx)
+            .await
+            .unwrap();
+    }
+
+    /// A fresh directory for snapshots of the test `name`.
+    fn snapshot_directory(name: &str) -> PathBuf {
+        let directory =
+            std::env::temp_dir().join(format!("achat-collector-{name}-{}", std::process::id()));
+        std::fs::create_dir_all(&directory).unwrap();
+        directory
+    }
+
+    #[tokio::test]
+    async fn saves_and_loads_snapshots() {
+        let directory = snapshot_directory("roundtrip");
+        let path = directory.join("collector.json");
+        assert_eq!(Collection::load(&path).unwrap(), Collection::default());
+
+        let mut collection = Collection::default();
+        collection.push(
+            "alice".to_string(),
+            "hello\n".to_string(),
+            Utc::now(),
+            &Retention::default(),
+        );
+        collection.push(
+            "bob".to_string(),
+            "hi\n".to_string(),
+            Utc::now(),
+            &Retention::default(),
+        );
+        collection.save(&path).await.unwrap();
+
+        assert_eq!(Collection::load(&path).unwrap(), collection);
+        assert!(!directory.join("collector.json.tmp").exists());
+        std::fs::remove_dir_all(&directory).unwrap();
+    }
+
+    #[tokio::test]
+    async fn snapshots_periodically_and_on_termination() {
+        let directory = snapshot_directory("periodic");
+        let snapshots = Snapshots {
+            path: directory.join("collector.json"),
+            interval: Duration::from_millis(10),
+        };
+        let (tx, rx) = mpsc::channel(16);
+        let collector = tokio::spawn(collect_with(
+            rx,
+            Collection::default(),
+            Some(snapshots.clone()),
+            Retention::default(),
+        ));
+
+        let text = |content: &str| Message::Text {
+            sender: "alice".to_string(),
+            content: content.to_string(),
+        };
+        tx.send(text("one")).await.unwrap();
+        tokio::time::timeout(Duration::from_secs(5), async {
+            while Collection::load(&snapshots.path).unwrap().is_empty() {
+                tokio::time::sleep(Duration::from_millis(5)).await;
+            }
+        })
+        .await
+        .unwrap();
+
+        tx.send(text("two")).await.unwrap();
+        drop(tx);
+        collector.await.unwrap().unwrap();
+        assert_eq!(Collection::load(&snapshots.path).unwrap().len(), 2);
+        std::fs::remove_dir_all(&directory).unwrap();
+    }
+
+    #[test]
+    fn rejects_corrupt_snapshot() {
+        let directory = snapshot_directory("corrupt");
+        let path = directory.join("collector.json");
+        std::fs::write(&path, "{").unwrap();
+        assert!(Collection::load(&path).is_err());
+        std::fs::remove_dir_all(&directory).unwrap();
+    }
+
+    /// Collect `messages` of (sender, content, minute after 12:00) under `retention`.
+    fn collection(retention: &Retention, messages: &[(&str, &str, u32)]) -> Collection {
+        let mut collection = Collection::default();
+        for (sender, content, minute) in messages {
+            let at = format!("2024-01-01T12:{minute:02}:00Z").parse().unwrap();
+            collection.push(sender.to_string(), content.to_string(), at, retention);
+        }
+        collection
+    }
+
+    fn contents(collection: &Collection) -> Vec<(&str, &str)> {
+        report(&collection.messages, &Query::default())
+            .into_iter()
+            .map(|(sender, entry)| (sender, entry.content.as_str()))
+            .collect()
+    }
+
+    #[test]
+    fn evicts_oldest_messages_per_sender() {
+        let retention = Retention {
+            max_per_sender: Some(2),
+            ..Retention::default()
+        };
+        let collection = collection(
+            &retention,
+            &[("a", "1", 0), ("b", "2", 1), ("a", "3", 2), ("a", "4", 3)],
+        );
+        assert_eq!(contents(&collection), [("a", "3"), ("a", "4"), ("b", "2")]);
+        assert_eq!(collection.evictions().per_sender, 1);
+        assert_eq!(collection.bytes(), 3);
+    }
+
+    #[test]
+    fn evicts_oldest_messages_over_memory_limit() {
+        let retention = Retention {
+            max_bytes: Some(5),
+            ..Retention::default()
+        };
+        let collection = collection(
+            &retention,
+            &[("a", "12", 0), ("b", "34", 1), ("a", "56", 2)],
+        );
+        assert_eq!(contents(&collection), [("a", "56"), ("b", "34")]);
+        assert_eq!(collection.evictions().memory, 1);
+        assert_eq!(collection.bytes(), 4);
+    }
+
+    #[test]
+    fn evicts_expired_messages() {
+        let retention = Retention {
+            max_age: Some(Duration::from_secs(90)),
+            ..Retention::default()
+        };
+        let mut collection = collection(
+            &retention,
+            &[("a", "old", 0), ("b", "older", 0), ("a", "new", 2)],
+        );
+        collection.enforce(&retention, "2024-01-01T12:02:00Z".parse().unwrap());
+
+        assert_eq!(contents(&collection), [("a", "new")]);
+        assert_eq!(
+            collection.evictions(),
+            Evictions {
+                age: 2,
+                per_sender: 0,
+                memory: 0
+            }
+        );
+        assert_eq!(
+            collection.evictions().to_string(),
+            "evicted 2 by age, 0 by sender limit, 0 by memory limit"
+        );
+        // Senders without messages are gone.
+        assert_eq!(collection.messages.len(), 1);
+    }
+
+    #[test]
+    fn keeps_sender_stats_after_eviction() {
+        let retention = Retention {
+            max_per_sender: Some(1),
+            ..Retention::default()
+        };
+        let collection = collection(
+            &retention,
+            &[("a", "1", 0), ("b", "22", 1), ("a", "3333", 2)],
+        );
+        let stats = collection.stats();
+        assert_eq!(
+            stats["a"],
+            SenderStats {
+                messages: 2,
+                bytes: 5,
+                first_seen: "2024-01-01T12:00:00Z".parse().unwrap(),
+                last_seen: "2024-01-01T12:02:00Z".parse().unwrap(),
+            }
+        );
+        assert_eq!(stats["a"].average_length(), 2.5);
+        assert_eq!(
+            format_stats(&stats),
+            "Stats for a: 2 message(s) of 5 byte(s) (2.5 on average), \
+             first seen 2024-01-01T12:00:00Z, last seen 2024-01-01T12:02:00Z\n\
+             Stats for b: 1 message(s) of 2 byte(s) (2.0 on average), \
+             first seen 2024-01-01T12:01:00Z, last seen 2024-01-01T12:01:00Z\n"
+        );
+        assert_eq!(format_stats(&BTreeMap::new()), "Stats: no senders yet\n");
+    }
+
+    #[test]
+    fn searches_kept_messages_only() {
+        let retention = Retention {
+            max_per_sender: Some(1),
+            ..Retention::default()
+        };
+        let collection = collection(
+            &retention,
+            &[
+                ("a", "disk full", 0),
+                ("b", "disk ok", 1),
+                ("a", "all good", 2),
+            ],
+        );
+        let matches = collection.search("disk", SEARCH_LIMIT);
+        assert_eq!(
+            format_matches(&matches),
+            "Found 1 match(es)\n2024-01-01T12:01:00Z b: disk ok\n"
+        );
+        assert_eq!(search_terms("search disk full\r\n"), Some("disk full"));
+        assert_eq!(search_terms("search\n"), Some(""));
+        assert_eq!(search_terms("searching\n"), None);
+    }
+
+    #[test]
+    fn validates_names() {
+        assert_eq!(hello_request("hello sensor-1\r\n"), Some("sensor-1"));
+        assert_eq!(hello_request("hello\n"), None);
+        assert!(validate_name("sensor_1.eu-west").is_ok());
+        for (name, error) in [
+            ("", "name must not be empty"),
+            (
+                "two words",
+                "unexpected ' ', use letters, digits, '-', '_' or '.'",
+            ),
+            (
+                "127.0.0.1:4000",
+                "unexpected ':', use letters, digits, '-', '_' or '.'",
+            ),
+        ] {
+            assert_eq!(validate_name(name).unwrap_err().to_string(), error);
+        }
+        assert!(validate_name(&"x".repeat(MAX_NAME_LENGTH + 1)).is_err());
+    }
+
+    #[tokio::test]
+    async fn claims_names_once() {
+        let (tx, rx) = mpsc::channel(16);
+        let collector = tokio::spawn(collect(rx));
+        let hello = move |name: &str| {
+            let tx = tx.clone();
+            let name = name.to_string();
+            async move {
+                let (reply, receiver) = oneshot::channel();
+                tx.send(Message::Hello { name, reply }).await.unwrap();
+                receiver.await.unwrap()
+            }
+        };
+
+        let claim = hello("alice").await.unwrap();
+        assert!(hello("alice").await.is_none());
+        assert!(hello("bob").await.is_some());
+        drop(claim);
+        assert!(hello("alice").await.is_some());
+
+        drop(hello);
+        collector.await.unwrap().unwrap();
+    }
+
+    #[test]
+    fn parses_follow_requests() {
+        assert_eq!(follow_request("follow\r\n"), Some(None));
+        assert_eq!(
+            follow_request("follow alice\n"),
+            Some(Some("alice".to_string()))
+        );
+        assert_eq!(follow_request("followers\n"), None);
+        assert_eq!(follow_request("unfollow\n"), None);
+        assert!(is_unfollow("unfollow\r\n"));
+    }
+
+    #[tokio::test]
+    async fn streams_followed_messages() {
+        let (tx, rx) = mpsc::channel(16);
+        let collector = tokio::spawn(collect(rx));
+
+        let (reply, receiver) = oneshot::channel();
+        tx.send(Message::Follow { reply }).await.unwrap();
+        let mut following = Following {
+            rx: receiver.await.unwrap(),
+            sender: Some("bob".to_string()),
+        };
+        for (sender, content) in [("alice", "hi bob\n"), ("bob", "hi alice\n")] {
+            tx.send(Message::Text {
+                sender: sender.to_string(),
+                content: content.to_string(),
+            })
+            .await
+            .unwrap();
+        }
+
+        let collected = following.next().await.unwrap();
+        assert_eq!(
+            (collected.sender.as_str(), collected.content.as_str()),
+            ("bob", "hi alice\n")
+        );
+        assert_eq!(
+            collected.to_string(),
+            format!(
+                "{} bob: hi alice\n",
+                collected.at.to_rfc3339_opts(SecondsFormat::AutoSi, true)
+            )
+        );
+
+        drop(tx);
+        collector.await.unwrap().unwrap();
+        assert!(matches!(following.next().await, Err(RecvError::Closed)));
+    }
+
+    #[tokio::test]
+    async fn reports_retention_state() {
+        let writer = Mock::new()
+            .write(
+                b"Retention: 1 message(s) of 4 byte(s) kept, evicted 0 by age, 1 by sender limit, 0 by memory limit\n",
+            )
+            .build();
+        let reader = Mock::new()
+            .read(b"one\n")
+            .read(b"two\n")
+            .read(b"retention\n")
+            .build();
+
+        let (tx, rx) = mpsc::channel(16);
+        let retention = Retention {
+            max_per_sender: Some(1),
+            ..Retention::default()
+        };
+        let server = tokio::spawn(collect_with(rx, Collection::default(), None, retention));
+        handle_connection("test".to_string(), reader, writer, tx)
+            .await
+            .unwrap();
+        server.await.unwrap().unwrap();
+    }
 }
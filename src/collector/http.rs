@@ -0,0 +1,346 @@
+use anyhow::Context;
+use axum::{
+    extract::{Query as UrlQuery, State},
+    http::{header, StatusCode},
+    response::{IntoResponse, Response},
+    routing::get,
+    Json, Router,
+};
+use serde::{Deserialize, Serialize};
+use std::{collections::BTreeMap, net::SocketAddr};
+use tokio::{
+    sync::{mpsc, oneshot},
+    task::JoinHandle,
+};
+use tokio_util::sync::CancellationToken;
+
+use super::{validate_name, Message, Query, ReportFormat, SenderStats};
+
+/// Routes talking to the collector on `tx`, just like [`super::handle_connection`]:
+///
+/// - `GET /senders`: JSON object mapping each sender to the number of its messages.
+/// - `GET /stats`: JSON object mapping each sender to its [`SenderStats`], plus its average message length.
+/// - `GET /messages`: report of the messages, taking the keys of a [`Query`] as URL parameters,
+///   such as `/messages?sender=alice&format=csv`.
+/// - `POST /messages`: collect the message in the JSON body, such as `{"sender": "alice", "content": "hello"}`.
+pub fn router(tx: mpsc::Sender<Message>) -> Router {
+    Router::new()
+        .route("/senders", get(senders))
+        .route("/stats", get(stats))
+        .route("/messages", get(messages).post(post_message))
+        .with_state(tx)
+}
+
+/// Bind `address` and serve the [`router`] on it in a background task, until `token` is cancelled.
+/// Returns the bound address and the task.
+///
+/// # Errors
+/// Returns an error if binding fails.
+pub fn serve(
+    address: SocketAddr,
+    tx: mpsc::Sender<Message>,
+    token: CancellationToken,
+) -> anyhow::Result<(SocketAddr, JoinHandle<anyhow::Result<()>>)> {
+    let listener = std::net::TcpListener::bind(address)
+        .with_context(|| format!("Failed to bind HTTP listener on {address}"))?;
+    listener
+        .set_nonblocking(true)
+        .context("Failed to configure HTTP listener")?;
+    let server = axum::Server::from_tcp(listener)
+        .context("Failed to create HTTP server")?
+        .serve(router(tx).into_make_service());
+    let local_addr = server.local_addr();
+    tracing::info!(address = %local_addr, "Serving collector over HTTP");
+
+    let task = tokio::spawn(async move {
+        server
+            .with_graceful_shutdown(token.cancelled_owned())
+            .await
+            .context("HTTP server failed")
+    });
+    Ok((local_addr, task))
+}
+
+/// A message posted to `/messages`.
+#[derive(Debug, Deserialize)]
+struct NewMessage {
+    sender: String,
+    content: String,
+}
+
+/// The [`SenderStats`] of a sender, as answered on `/stats`.
+#[derive(Debug, Serialize)]
+struct Metrics {
+    #[serde(flatten)]
+    stats: SenderStats,
+    average_length: f64,
+}
+
+/// A failed request, answered with its status and an explanation.
+#[derive(Debug)]
+struct Error(StatusCode, String);
+
+impl Error {
+    fn unavailable() -> Self {
+        Self(
+            StatusCode::SERVICE_UNAVAILABLE,
+            "Collector is gone".to_string(),
+        )
+    }
+}
+
+impl IntoResponse for Error {
+    fn into_response(self) -> Response {
+        (self.0, format!("{}\n", self.1)).into_response()
+    }
+}
+
+/// Send the request built by `message` to the collector and await its reply.
+async fn request<T>(
+    tx: &mpsc::Sender<Message>,
+    message: impl FnOnce(oneshot::Sender<T>) -> Message,
+) -> Result<T, Error> {
+    let (reply, receiver) = oneshot::channel();
+    tx.send(message(reply))
+        .await
+        .map_err(|_| Error::unavailable())?;
+    receiver.await.map_err(|_| Error::unavailable())
+}
+
+async fn senders(
+    State(tx): State<mpsc::Sender<Message>>,
+) -> Result<Json<BTreeMap<String, usize>>, Error> {
+    request(&tx, |reply| Message::Senders { reply })
+        .await
+        .map(Json)
+}
+
+async fn stats(
+    State(tx): State<mpsc::Sender<Message>>,
+) -> Result<Json<BTreeMap<String, Metrics>>, Error> {
+    let stats = request(&tx, |reply| Message::Stats { reply }).await?;
+    Ok(Json(
+        stats
+            .into_iter()
+            .map(|(sender, stats)| {
+                let average_length = stats.average_length();
+                (
+                    sender,
+                    Metrics {
+                        stats,
+                        average_length,
+                    },
+                )
+            })
+            .collect(),
+    ))
+}
+
+async fn messages(
+    State(tx): State<mpsc::Sender<Message>>,
+    UrlQuery(parameters): UrlQuery<Vec<(String, String)>>,
+) -> Result<Response, Error> {
+    let mut query = Query::default();
+    for (key, value) in &parameters {
+        query
+            .set(key, value)
+            .map_err(|error| Error(StatusCode::BAD_REQUEST, format!("Invalid query: {error:#}")))?;
+    }
+    let content_type = match query.format {
+        ReportFormat::Json => "application/json",
+        ReportFormat::Jsonl => "application/x-ndjson",
+        ReportFormat::Csv => "text/csv",
+        ReportFormat::Yaml => "application/yaml",
+        ReportFormat::Text => "text/plain; charset=utf-8",
+    };
+    let report = request(&tx, |reply| Message::Report { query, reply })
+        .await?
+        .map_err(|error| Error(StatusCode::INTERNAL_SERVER_ERROR, error))?;
+    Ok(([(header::CONTENT_TYPE, content_type)], report).into_response())
+}
+
+async fn post_message(
+    State(tx): State<mpsc::Sender<Message>>,
+    Json(message): Json<NewMessage>,
+) -> Result<StatusCode, Error> {
+    validate_name(&message.sender).map_err(|error| {
+        Error(
+            StatusCode::BAD_REQUEST,
+            format!("Invalid sender: {error:#}"),
+        )
+    })?;
+    let name = message.sender.clone();
+    if request(&tx, |reply| Message::Claimed { name, reply }).await? {
+        return Err(Error(
+            StatusCode::CONFLICT,
+            format!("Sender {} is claimed by a connection", message.sender),
+        ));
+    }
+    tx.send(Message::Text {
+        sender: message.sender,
+        content: message.content,
+    })
+    .await
+    .map_err(|_| Error::unavailable())?;
+    Ok(StatusCode::ACCEPTED)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use axum::{body::Body, http::Request};
+    use tower::ServiceExt;
+
+    /// A router in front of a running collector.
+    fn app() -> Router {
+        let (tx, rx) = mpsc::channel(16);
+        tokio::spawn(super::super::collect(rx));
+        router(tx)
+    }
+
+    /// Send `request` to `app`, returning the status and body.
+    async fn send(app: &Router, request: Request<Body>) -> (StatusCode, String) {
+        let response = app.clone().oneshot(request).await.unwrap();
+        let status = response.status();
+        let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+        (status, String::from_utf8(body.to_vec()).unwrap())
+    }
+
+    fn get(uri: &str) -> Request<Body> {
+        Request::get(uri).body(Body::empty()).unwrap()
+    }
+
+    fn post(body: &str) -> Request<Body> {
+        Request::post("/messages")
+            .header(header::CONTENT_TYPE, "application/json")
+            .body(Body::from(body.to_string()))
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn posts_and_gets_messages() {
+        let app = app();
+        for body in [
+            r#"{"sender": "alice", "content": "hello"}"#,
+            r#"{"sender": "bob", "content": "hi"}"#,
+            r#"{"sender": "alice", "content": "bye"}"#,
+        ] {
+            assert_eq!(send(&app, post(body)).await.0, StatusCode::ACCEPTED);
+        }
+
+        let (status, body) = send(&app, get("/senders")).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body, r#"{"alice":2,"bob":1}"#);
+
+        let (status, body) = send(&app, get("/stats")).await;
+        assert_eq!(status, StatusCode::OK);
+        let stats = serde_json::from_str::<serde_json::Value>(&body).unwrap();
+        assert_eq!(stats["alice"]["messages"], 2);
+        assert_eq!(stats["alice"]["bytes"], 8);
+        assert_eq!(stats["alice"]["average_length"], 4.0);
+        assert!(stats["bob"]["first_seen"].is_string());
+
+        let (status, body) = send(&app, get("/messages?sender=alice&last=1")).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(
+            serde_json::from_str::<serde_json::Value>(&body).unwrap(),
+            serde_json::json!({ "alice": ["bye"] })
+        );
+    }
+
+    #[tokio::test]
+    async fn answers_in_requested_format() {
+        let app = app();
+        send(&app, post(r#"{"sender": "alice", "content": "a, b"}"#)).await;
+
+        let response = app
+            .clone()
+            .oneshot(get("/messages?format=csv&grep=a%2C%20b"))
+            .await
+            .unwrap();
+        assert_eq!(response.headers()[header::CONTENT_TYPE], "text/csv");
+        let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+        let lines = std::str::from_utf8(&body)
+            .unwrap()
+            .lines()
+            .collect::<Vec<_>>();
+        assert_eq!(lines[0], "sender,at,content");
+        assert!(
+            lines[1].starts_with("alice,") && lines[1].ends_with(",\"a, b\""),
+            "{lines:?}"
+        );
+    }
+
+    #[tokio::test]
+    async fn rejects_invalid_requests() {
+        let app = app();
+        assert_eq!(
+            send(&app, get("/messages?last=few")).await,
+            (
+                StatusCode::BAD_REQUEST,
+                "Invalid query: invalid count \"few\": invalid digit found in string\n".to_string()
+            )
+        );
+        assert_eq!(
+            send(&app, post(r#"{"sender": "", "content": "hello"}"#)).await,
+            (
+                StatusCode::BAD_REQUEST,
+                "Invalid sender: name must not be empty\n".to_string()
+            )
+        );
+        assert_eq!(
+            send(
+                &app,
+                post(r#"{"sender": "127.0.0.1:4000", "content": "hello"}"#)
+            )
+            .await,
+            (
+                StatusCode::BAD_REQUEST,
+                "Invalid sender: unexpected ':', use letters, digits, '-', '_' or '.'\n"
+                    .to_string()
+            )
+        );
+        assert_eq!(
+            send(&app, post(r#"{"sender": "alice"}"#)).await.0,
+            StatusCode::UNPROCESSABLE_ENTITY
+        );
+    }
+
+    #[tokio::test]
+    async fn rejects_senders_claimed_by_connections() {
+        let (tx, rx) = mpsc::channel(16);
+        tokio::spawn(super::super::collect(rx));
+        let app = router(tx.clone());
+        let body = r#"{"sender": "alice", "content": "hello"}"#;
+
+        let claim = request(&tx, |reply| Message::Hello {
+            name: "alice".to_string(),
+            reply,
+        })
+        .await
+        .unwrap()
+        .unwrap();
+        assert_eq!(
+            send(&app, post(body)).await,
+            (
+                StatusCode::CONFLICT,
+                "Sender alice is claimed by a connection\n".to_string()
+            )
+        );
+
+        drop(claim);
+        assert_eq!(send(&app, post(body)).await.0, StatusCode::ACCEPTED);
+    }
+
+    #[tokio::test]
+    async fn accepts_concurrent_posts_of_one_sender() {
+        let app = app();
+        let body = r#"{"sender": "alice", "content": "hello"}"#;
+
+        let posts = (0..8).map(|_| send(&app, post(body)));
+        for (status, _) in futures::future::join_all(posts).await {
+            assert_eq!(status, StatusCode::ACCEPTED);
+        }
+        assert_eq!(send(&app, get("/senders")).await.1, r#"{"alice":8}"#);
+    }
+}
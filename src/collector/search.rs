@@ -0,0 +1,238 @@
+use chrono::{DateTime, Utc};
+use std::{
+    collections::{HashMap, VecDeque},
+    fmt::{self, Display},
+};
+
+use super::Entry;
+
+/// Bytes of message content shown around the first matching term.
+const SNIPPET_LENGTH: usize = 80;
+
+/// A message matching a search, best matches first.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Match {
+    /// Origin of message.
+    pub sender: String,
+    /// When the message was received.
+    pub at: DateTime<Utc>,
+    /// Part of the message around the first matching term.
+    pub snippet: String,
+    /// Relevance of the message, higher is better.
+    pub score: f64,
+}
+
+impl Display for Match {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} {}: {}",
+            self.at.to_rfc3339_opts(chrono::SecondsFormat::AutoSi, true),
+            self.sender,
+            self.snippet
+        )
+    }
+}
+
+/// Inverted index of the collected messages: the messages containing each term.
+///
+/// Must be told about every message added to or evicted from the collection, see [`Index::insert`] and [`Index::remove`].
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub(super) struct Index {
+    /// For each term, how often it occurs in each message, by sequence number.
+    postings: HashMap<String, HashMap<u64, u32>>,
+    /// Sender of each indexed message, by sequence number.
+    senders: HashMap<u64, String>,
+}
+
+impl Index {
+    /// Index the `entry` of `sender`.
+    pub(super) fn insert(&mut self, sender: &str, entry: &Entry) {
+        for (_, term) in terms(&entry.content) {
+            *self
+                .postings
+                .entry(term)
+                .or_default()
+                .entry(entry.seq)
+                .or_default() += 1;
+        }
+        self.senders.insert(entry.seq, sender.to_string());
+    }
+
+    /// Forget the evicted `entry`.
+    pub(super) fn remove(&mut self, entry: &Entry) {
+        for (_, term) in terms(&entry.content) {
+            if let Some(messages) = self.postings.get_mut(&term) {
+                messages.remove(&entry.seq);
+                if messages.is_empty() {
+                    self.postings.remove(&term);
+                }
+            }
+        }
+        self.senders.remove(&entry.seq);
+    }
+
+    /// The at most `limit` messages best matching any of the terms in `query`, looked up in `messages`.
+    ///
+    /// Messages score higher the more often they contain the terms, rare terms weighing more than common ones.
+    /// Equally good matches are ordered newest first.
+    pub(super) fn search(
+        &self,
+        query: &str,
+        messages: &HashMap<String, VecDeque<Entry>>,
+        limit: usize,
+    ) -> Vec<Match> {
+        let mut query = terms(query).map(|(_, term)| term).collect::<Vec<_>>();
+        query.sort_unstable();
+        query.dedup();
+
+        let total = self.senders.len() as f64;
+        let mut scores: HashMap<u64, f64> = HashMap::new();
+        for term in &query {
+            let Some(postings) = self.postings.get(term) else {
+                continue;
+            };
+            let weight = (1.0 + total / postings.len() as f64).ln();
+            for (seq, count) in postings {
+                *scores.entry(*seq).or_default() += f64::from(*count) * weight;
+            }
+        }
+        let mut ranked = scores.into_iter().collect::<Vec<_>>();
+        ranked.sort_unstable_by(|(a_seq, a), (b_seq, b)| b.total_cmp(a).then(b_seq.cmp(a_seq)));
+
+        ranked
+            .into_iter()
+            .filter_map(|(seq, score)| {
+                let sender = self.senders.get(&seq)?;
+                let entries = messages.get(sender)?;
+                let index = entries.binary_search_by_key(&seq, |entry| entry.seq).ok()?;
+                let entry = &entries[index];
+                Some(Match {
+                    sender: sender.clone(),
+                    at: entry.at,
+                    snippet: snippet(&entry.content, &query),
+                    score,
+                })
+            })
+            .take(limit)
+            .collect()
+    }
+}
+
+/// The words of `text` (runs of alphanumeric characters) with their byte offsets, lowercased.
+fn terms(text: &str) -> impl Iterator<Item = (usize, String)> + '_ {
+    text.split(|c: char| !c.is_alphanumeric())
+        .filter(|word| !word.is_empty())
+        .map(move |word| {
+            // `word` is a slice of `text`.
+            let offset = word.as_ptr() as usize - text.as_ptr() as usize;
+            (offset, word.to_lowercase())
+        })
+}
+
+/// About [`SNIPPET_LENGTH`] bytes of the single line `content`, starting a little before the first term in `query`.
+fn snippet(content: &str, query: &[String]) -> String {
+    let content = content.trim_end_matches(['\r', '\n']);
+    let first = terms(content)
+        .find(|(_, term)| query.contains(term))
+        .map_or(0, |(offset, _)| offset);
+    let start = floor_char_boundary(content, first.saturating_sub(SNIPPET_LENGTH / 4));
+    let end = floor_char_boundary(content, start + SNIPPET_LENGTH);
+    let mut snippet = String::new();
+    if start > 0 {
+        snippet.push_str("...");
+    }
+    snippet.push_str(&content[start..end]);
+    if end < content.len() {
+        snippet.push_str("...");
+    }
+    snippet
+}
+
+/// The largest character boundary of `text` at or before `index`.
+fn floor_char_boundary(text: &str, index: usize) -> usize {
+    if index >= text.len() {
+        return text.len();
+    }
+    (0..=index)
+        .rev()
+        .find(|&index| text.is_char_boundary(index))
+        .unwrap_or(0)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn entry(seq: u64, content: &str) -> Entry {
+        Entry {
+            seq,
+            at: format!("2024-01-01T12:00:{seq:02}Z").parse().unwrap(),
+            content: content.to_string(),
+        }
+    }
+
+    /// Index `messages` of (sender, content), numbered in order.
+    fn indexed(messages: &[(&str, &str)]) -> (Index, HashMap<String, VecDeque<Entry>>) {
+        let mut index = Index::default();
+        let mut map: HashMap<String, VecDeque<Entry>> = HashMap::new();
+        for (seq, (sender, content)) in messages.iter().enumerate() {
+            let entry = entry(seq as u64, content);
+            index.insert(sender, &entry);
+            map.entry(sender.to_string()).or_default().push_back(entry);
+        }
+        (index, map)
+    }
+
+    fn found(index: &Index, query: &str, map: &HashMap<String, VecDeque<Entry>>) -> Vec<String> {
+        index
+            .search(query, map, 10)
+            .into_iter()
+            .map(|found| format!("{}: {}", found.sender, found.snippet))
+            .collect()
+    }
+
+    #[test]
+    fn ranks_matches() {
+        let (index, map) = indexed(&[
+            ("a", "Disk full\n"),
+            ("b", "disk FULL, disk full!\n"),
+            ("a", "all good\n"),
+            ("c", "disk ok\n"),
+        ]);
+        assert_eq!(
+            found(&index, "disk full", &map),
+            ["b: disk FULL, disk full!", "a: Disk full", "c: disk ok"]
+        );
+        assert_eq!(found(&index, "good good", &map), ["a: all good"]);
+        assert!(found(&index, "missing", &map).is_empty());
+        assert!(found(&index, "", &map).is_empty());
+    }
+
+    #[test]
+    fn forgets_removed_messages() {
+        let (mut index, mut map) = indexed(&[("a", "disk full"), ("b", "disk ok")]);
+        let evicted = map.get_mut("a").unwrap().pop_front().unwrap();
+        index.remove(&evicted);
+
+        assert_eq!(found(&index, "disk", &map), ["b: disk ok"]);
+        assert!(!index.postings.contains_key("full"));
+        assert_eq!(index.senders.len(), 1);
+    }
+
+    #[test]
+    fn cuts_snippets_around_first_match() {
+        let long = format!("{} needle {}", "x".repeat(100), "y".repeat(100));
+        let cut = snippet(&long, &["needle".to_string()]);
+        assert!(
+            cut.starts_with("...xxx") && cut.ends_with("yyy..."),
+            "{cut}"
+        );
+        assert!(cut.contains("needle"));
+        assert_eq!(cut.len(), 3 + SNIPPET_LENGTH + 3);
+
+        // Never cut through a character.
+        let umlauts = "ü".repeat(100);
+        assert!(snippet(&umlauts, &[]).len() <= SNIPPET_LENGTH + 3);
+    }
+}
@@ -0,0 +1,564 @@
+use anyhow::{bail, ensure, Context};
+use serde::Deserialize;
+use std::{
+    net::SocketAddr,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use crate::{
+    chat_with_announce::{Announcement, Schedule, TopicSetter},
+    collector::{Retention, Snapshots},
+    LogFormat,
+};
+
+/// Server configuration, usually read from a TOML file.
+///
+/// Every section and every field is optional, missing values fall back to their defaults:
+///
+/// ```toml
+/// [server]
+/// address = "127.0.0.1:8080"
+/// console = "127.0.0.1:6669"
+/// log = "achat=debug,info"
+/// log_format = "json"
+/// capacity = 16
+/// max_connections = 1000
+/// grace_period_secs = 5
+/// motd = "motd.txt"
+/// quit = "quit"
+/// idle_timeout_secs = 600
+///
+/// [chat_with_announce]
+/// topic = "Chat topic"
+/// topic_setters = ["127.0.0.1"]
+/// interval_secs = 10
+///
+/// [[chat_with_announce.announcements]]
+/// text = "{clients} client(s) online"
+/// interval_secs = 60
+///
+/// [[chat_with_announce.announcements]]
+/// text = "It is {time}"
+/// cron = "0 0 * * * *"
+///
+/// [chat_with_cancel]
+/// termination_phrase = "call it a day"
+/// secret = "hunter2"
+///
+/// [collector]
+/// snapshot = "collector.json"
+/// snapshot_interval_secs = 60
+/// max_age_secs = 86400
+/// max_messages_per_sender = 1000
+/// max_bytes = 100000000
+/// http = "127.0.0.1:8081"
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    /// Settings shared by all servers.
+    pub server: ServerConfig,
+
+    /// Settings of [`crate::chat_with_announce`].
+    pub chat_with_announce: AnnounceConfig,
+
+    /// Settings of [`crate::chat_with_cancel`].
+    pub chat_with_cancel: CancelConfig,
+
+    /// Settings of [`crate::collector`].
+    pub collector: CollectorConfig,
+
+    /// File the configuration was read from, if any. Not a setting of the file itself.
+    #[serde(skip)]
+    pub path: Option<PathBuf>,
+}
+
+/// Settings shared by all servers.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ServerConfig {
+    /// Address to listen on.
+    pub address: SocketAddr,
+
+    /// Address to publish console events on.
+    pub console: Option<SocketAddr>,
+
+    /// Log filter, see [`crate::init_logging`].
+    pub log: Option<String>,
+
+    /// Format of log output.
+    pub log_format: LogFormat,
+
+    /// Capacity of the channels connecting clients to each other or to the server.
+    pub capacity: usize,
+
+    /// Maximum number of clients served at the same time, unlimited if `None`.
+    pub max_connections: Option<usize>,
+
+    /// Seconds connections get to finish after shutdown, before they are aborted.
+    pub grace_period_secs: u64,
+
+    /// File with the message of the day, sent to clients of the chat servers on connect.
+    pub motd: Option<PathBuf>,
+
+    /// Line which disconnects a client of the chat servers.
+    pub quit: String,
+
+    /// Seconds after which a client of the chat servers that has not sent a line is disconnected, never if not given.
+    pub idle_timeout_secs: Option<u64>,
+}
+
+impl ServerConfig {
+    /// Time connections get to finish after shutdown, before they are aborted.
+    pub fn grace_period(&self) -> Duration {
+        Duration::from_secs(self.grace_period_secs)
+    }
+
+    /// Time after which an idle client of the chat servers is disconnected, if ever.
+    pub fn idle_timeout(&self) -> Option<Duration> {
+        self.idle_timeout_secs.map(Duration::from_secs)
+    }
+}
+
+impl Default for ServerConfig {
+    fn default() -> Self {
+        Self {
+            address: SocketAddr::from(([127, 0, 0, 1], 8080)),
+            console: None,
+            log: None,
+            log_format: LogFormat::default(),
+            capacity: 16,
+            max_connections: None,
+            grace_period_secs: 5,
+            motd: None,
+            quit: "quit".to_string(),
+            idle_timeout_secs: None,
+        }
+    }
+}
+
+/// Settings of [`crate::chat_with_announce`].
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct AnnounceConfig {
+    /// Initial topic.
+    pub topic: String,
+
+    /// Clients allowed to change the topic with `/topic`, by identity or address without port, see [`TopicSetter`].
+    /// Anyone may change it if not given.
+    pub topic_setters: Option<Vec<String>>,
+
+    /// Seconds between uptime and statistics announcements, unless `announcements` are given.
+    pub interval_secs: u64,
+
+    /// Scheduled announcements, replacing the uptime and statistics announcement.
+    pub announcements: Vec<AnnouncementConfig>,
+}
+
+impl AnnounceConfig {
+    /// Period between uptime and statistics announcements.
+    pub fn interval(&self) -> Duration {
+        Duration::from_secs(self.interval_secs)
+    }
+
+    /// The clients allowed to change the topic, anyone if `None`.
+    ///
+    /// # Errors
+    /// Returns an error if a setter is neither an address nor a client identity.
+    pub fn topic_setters(&self) -> anyhow::Result<Option<Vec<TopicSetter>>> {
+        let Some(setters) = &self.topic_setters else {
+            return Ok(None);
+        };
+        setters
+            .iter()
+            .enumerate()
+            .map(|(i, setter)| {
+                setter
+                    .parse()
+                    .with_context(|| format!("Invalid chat_with_announce.topic_setters[{i}]"))
+            })
+            .collect::<anyhow::Result<_>>()
+            .map(Some)
+    }
+
+    /// The configured announcements, or an uptime and statistics announcement every `interval_secs` if there are none.
+    ///
+    /// # Errors
+    /// Returns an error if an announcement has no valid schedule.
+    pub fn announcements(&self) -> anyhow::Result<Vec<Announcement>> {
+        if self.announcements.is_empty() {
+            return Ok(vec![Announcement {
+                text: "Up for {uptime}, {stats}.".to_string(),
+                schedule: Schedule::Every(self.interval()),
+            }]);
+        }
+        self.announcements
+            .iter()
+            .enumerate()
+            .map(|(i, announcement)| {
+                announcement
+                    .to_announcement()
+                    .with_context(|| format!("Invalid chat_with_announce.announcements[{i}]"))
+            })
+            .collect()
+    }
+}
+
+/// A scheduled announcement, with exactly one of `interval_secs` and `cron`.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct AnnouncementConfig {
+    /// Announced text, see [`Announcement`] for placeholders.
+    pub text: String,
+
+    /// Seconds between announcements.
+    pub interval_secs: Option<u64>,
+
+    /// Cron expression with seconds, evaluated in UTC, such as `0 0 * * * *`.
+    pub cron: Option<String>,
+}
+
+impl AnnouncementConfig {
+    fn to_announcement(&self) -> anyhow::Result<Announcement> {
+        let schedule = match (self.interval_secs, &self.cron) {
+            (Some(0), None) => bail!("interval_secs must be greater than zero"),
+            (Some(secs), None) => Schedule::Every(Duration::from_secs(secs)),
+            (None, Some(cron)) => Schedule::Cron(Box::new(
+                cron.parse()
+                    .with_context(|| format!("Invalid cron expression {cron:?}"))?,
+            )),
+            _ => bail!("exactly one of interval_secs and cron must be given"),
+        };
+        Ok(Announcement {
+            text: self.text.clone(),
+            schedule,
+        })
+    }
+}
+
+impl Default for AnnounceConfig {
+    fn default() -> Self {
+        Self {
+            topic: "Chat topic".to_string(),
+            topic_setters: None,
+            interval_secs: 10,
+            announcements: Vec::new(),
+        }
+    }
+}
+
+/// Settings of [`crate::chat_with_cancel`].
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct CancelConfig {
+    /// Phrase which, followed by a space and the `secret`, shuts down the server.
+    pub termination_phrase: String,
+
+    /// Shared secret authorizing a shutdown. Without a secret, clients can not shut down the server.
+    pub secret: Option<String>,
+}
+
+impl Default for CancelConfig {
+    fn default() -> Self {
+        Self {
+            termination_phrase: "call it a day".to_string(),
+            secret: None,
+        }
+    }
+}
+
+/// Settings of [`crate::collector`].
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct CollectorConfig {
+    /// File to snapshot the collected messages to, and restore them from on startup.
+    /// Messages are only kept in memory if not given.
+    pub snapshot: Option<PathBuf>,
+
+    /// Seconds between snapshots.
+    pub snapshot_interval_secs: u64,
+
+    /// Seconds after which messages are evicted, kept forever if not given.
+    pub max_age_secs: Option<u64>,
+
+    /// Number of messages kept per sender, unlimited if not given.
+    pub max_messages_per_sender: Option<usize>,
+
+    /// Message bytes kept over all senders, unlimited if not given.
+    /// Only the message texts count, not the search index and per-sender bookkeeping kept alongside them,
+    /// so the memory used grows beyond this.
+    pub max_bytes: Option<usize>,
+
+    /// Address to serve the HTTP API on (see [`crate::collector::http`]), none if not given.
+    pub http: Option<SocketAddr>,
+}
+
+impl CollectorConfig {
+    /// Where and how often to snapshot, if at all.
+    pub fn snapshots(&self) -> Option<Snapshots> {
+        self.snapshot.as_ref().map(|path| Snapshots {
+            path: path.clone(),
+            interval: Duration::from_secs(self.snapshot_interval_secs),
+        })
+    }
+
+    /// Which messages to keep.
+    pub fn retention(&self) -> Retention {
+        Retention {
+            max_age: self.max_age_secs.map(Duration::from_secs),
+            max_per_sender: self.max_messages_per_sender,
+            max_bytes: self.max_bytes,
+        }
+    }
+}
+
+impl Default for CollectorConfig {
+    fn default() -> Self {
+        Self {
+            snapshot: None,
+            snapshot_interval_secs: 60,
+            max_age_secs: None,
+            max_messages_per_sender: None,
+            max_bytes: None,
+            http: None,
+        }
+    }
+}
+
+impl Config {
+    /// Read and validate the configuration file at `path`.
+    ///
+    /// # Errors
+    /// Returns an error if the file can not be read, parsed, or contains invalid values.
+    pub fn from_file(path: impl AsRef<Path>) -> anyhow::Result<Self> {
+        let path = path.as_ref();
+        let content = std::fs::read_to_string(path)
+            .with_context(|| format!("Failed to read config file {}", path.display()))?;
+        let mut config: Self = toml::from_str(&content)
+            .with_context(|| format!("Failed to parse config file {}", path.display()))?;
+        config.path = Some(path.to_path_buf());
+        config
+            .validate()
+            .with_context(|| format!("Invalid config file {}", path.display()))?;
+        Ok(config)
+    }
+
+    /// Check that all values are usable.
+    ///
+    /// # Errors
+    /// Returns an error naming the first offending field.
+    pub fn validate(&self) -> anyhow::Result<()> {
+        ensure!(
+            self.server.capacity > 0,
+            "server.capacity must be greater than zero"
+        );
+        ensure!(
+            self.server.max_connections != Some(0),
+            "server.max_connections must be greater than zero"
+        );
+        ensure!(
+            !self.server.quit.trim().is_empty(),
+            "server.quit must not be empty"
+        );
+        ensure!(
+            !self.server.quit.contains(['\r', '\n']),
+            "server.quit must be a single line"
+        );
+        ensure!(
+            self.server.idle_timeout_secs != Some(0),
+            "server.idle_timeout_secs must be greater than zero"
+        );
+        ensure!(
+            self.chat_with_announce.interval_secs > 0,
+            "chat_with_announce.interval_secs must be greater than zero"
+        );
+        self.chat_with_announce.topic_setters()?;
+        self.chat_with_announce.announcements()?;
+        ensure!(
+            !self.chat_with_cancel.termination_phrase.trim().is_empty(),
+            "chat_with_cancel.termination_phrase must not be empty"
+        );
+        ensure!(
+            self.chat_with_cancel.secret.as_deref() != Some(""),
+            "chat_with_cancel.secret must not be empty"
+        );
+        ensure!(
+            self.collector.snapshot_interval_secs > 0,
+            "collector.snapshot_interval_secs must be greater than zero"
+        );
+        ensure!(
+            self.collector.max_age_secs != Some(0),
+            "collector.max_age_secs must be greater than zero"
+        );
+        ensure!(
+            self.collector.max_messages_per_sender != Some(0),
+            "collector.max_messages_per_sender must be greater than zero"
+        );
+        ensure!(
+            self.collector.max_bytes != Some(0),
+            "collector.max_bytes must be greater than zero"
+        );
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn empty_file_is_default() {
+        assert_eq!(toml::from_str::<Config>("").unwrap(), Config::default());
+    }
+
+    #[test]
+    fn parses_all_sections() {
+        let config: Config = toml::from_str(
+            r#"
+            [server]
+            address = "0.0.0.0:9000"
+            log_format = "json"
+            capacity = 64
+            grace_period_secs = 2
+            motd = "/etc/achat/motd.txt"
+            quit = "/bye"
+            idle_timeout_secs = 300
+
+            [chat_with_announce]
+            topic = "Rust"
+            interval_secs = 3
+
+            [[chat_with_announce.announcements]]
+            text = "{clients} online"
+            interval_secs = 60
+
+            [[chat_with_announce.announcements]]
+            text = "It is {time}"
+            cron = "0 0 * * * *"
+
+            [chat_with_cancel]
+            termination_phrase = "stop"
+            secret = "s3cret"
+
+            [collector]
+            snapshot = "collector.json"
+            snapshot_interval_secs = 30
+            max_age_secs = 3600
+            max_bytes = 1024
+            http = "127.0.0.1:8081"
+            "#,
+        )
+        .unwrap();
+        assert_eq!(config.server.address, "0.0.0.0:9000".parse().unwrap());
+        assert_eq!(config.server.log_format, LogFormat::Json);
+        assert_eq!(config.server.capacity, 64);
+        assert_eq!(config.server.console, None);
+        assert_eq!(config.server.grace_period(), Duration::from_secs(2));
+        assert_eq!(
+            config.server.motd,
+            Some(PathBuf::from("/etc/achat/motd.txt"))
+        );
+        assert_eq!(config.server.quit, "/bye");
+        assert_eq!(config.server.idle_timeout(), Some(Duration::from_secs(300)));
+        assert_eq!(config.chat_with_announce.topic, "Rust");
+        assert_eq!(config.chat_with_announce.interval(), Duration::from_secs(3));
+        let announcements = config.chat_with_announce.announcements().unwrap();
+        assert_eq!(announcements.len(), 2);
+        assert_eq!(
+            announcements[0].schedule,
+            Schedule::Every(Duration::from_secs(60))
+        );
+        assert!(matches!(announcements[1].schedule, Schedule::Cron(_)));
+        assert_eq!(config.chat_with_cancel.termination_phrase, "stop");
+        assert_eq!(config.chat_with_cancel.secret.as_deref(), Some("s3cret"));
+        assert_eq!(
+            config.collector.snapshots(),
+            Some(Snapshots {
+                path: PathBuf::from("collector.json"),
+                interval: Duration::from_secs(30),
+            })
+        );
+        assert_eq!(
+            config.collector.http,
+            Some("127.0.0.1:8081".parse().unwrap())
+        );
+        assert_eq!(
+            config.collector.retention(),
+            Retention {
+                max_age: Some(Duration::from_secs(3600)),
+                max_per_sender: None,
+                max_bytes: Some(1024),
+            }
+        );
+    }
+
+    #[test]
+    fn rejects_unknown_fields() {
+        assert!(toml::from_str::<Config>("[server]\nadress = \"0.0.0.0:9000\"").is_err());
+    }
+
+    #[test]
+    fn rejects_zero_capacity() {
+        let config: Config = toml::from_str("[server]\ncapacity = 0").unwrap();
+        let error = config.validate().unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "server.capacity must be greater than zero"
+        );
+    }
+
+    #[test]
+    fn rejects_zero_collector_limits() {
+        for limit in [
+            "snapshot_interval_secs",
+            "max_age_secs",
+            "max_messages_per_sender",
+            "max_bytes",
+        ] {
+            let config: Config = toml::from_str(&format!("[collector]\n{limit} = 0")).unwrap();
+            let error = config.validate().unwrap_err();
+            assert_eq!(
+                error.to_string(),
+                format!("collector.{limit} must be greater than zero")
+            );
+        }
+    }
+
+    #[test]
+    fn rejects_multiline_quit() {
+        let config: Config = toml::from_str("[server]\nquit = \"bye\\nnow\"").unwrap();
+        let error = config.validate().unwrap_err();
+        assert_eq!(error.to_string(), "server.quit must be a single line");
+    }
+
+    #[test]
+    fn rejects_invalid_topic_setter() {
+        let config: Config =
+            toml::from_str("[chat_with_announce]\ntopic_setters = [\"::1\", \"localhost\"]")
+                .unwrap();
+        let error = config.validate().unwrap_err();
+        assert!(
+            format!("{error:#}").starts_with("Invalid chat_with_announce.topic_setters[1]: "),
+            "{error:#}"
+        );
+    }
+
+    #[test]
+    fn rejects_announcement_with_two_schedules() {
+        let config: Config = toml::from_str(
+            r#"
+            [[chat_with_announce.announcements]]
+            text = "hi"
+            interval_secs = 60
+            cron = "0 0 * * * *"
+            "#,
+        )
+        .unwrap();
+        let error = config.validate().unwrap_err();
+        assert_eq!(
+            format!("{error:#}"),
+            "Invalid chat_with_announce.announcements[0]: exactly one of interval_secs and cron must be given"
+        );
+    }
+}
@@ -4,10 +4,20 @@
 //! implement simple networking applications.
 
 pub use arguments::Arguments;
+pub use config::{
+    AnnounceConfig, AnnouncementConfig, CancelConfig, CollectorConfig, Config, ServerConfig,
+};
+pub use logging::{init_logging, LogFormat};
 use std::net::SocketAddr;
-use std::time::Duration;
+use std::{io, time::Duration};
+use tokio::{
+    io::{AsyncBufRead, AsyncBufReadExt, AsyncReadExt},
+    time::Instant,
+};
 
 mod arguments;
+mod config;
+mod logging;
 
 /// Initialize the console subscriber at the address indicated.
 pub fn init_console_subscriber(addr: impl Into<SocketAddr>) {
@@ -19,20 +29,96 @@ pub fn init_console_subscriber(addr: impl Into<SocketAddr>) {
 
 /// Is it a quit message?
 /// If the `line` is `"quit"` or `"quit\n"` or `"quit\r\n"`, return `true`.
+#[deprecated(note = "use `Session::is_quit`, which honours the configured quit phrase")]
 pub fn is_quit(line: &str) -> bool {
-    line == "quit" || line == "quit\n" || line == "quit\r\n"
+    Session::default().is_quit(line)
+}
+
+/// How clients of the chat servers end their session: by sending the `quit` phrase, or by staying idle too long.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Session {
+    /// Line which makes the server disconnect the client.
+    pub quit: String,
+    /// Time after which a client that has not sent a line is disconnected, never if `None`.
+    pub idle_timeout: Option<Duration>,
+}
+
+impl Session {
+    /// Session as configured in the `[server]` section.
+    pub fn from_config(config: &ServerConfig) -> Self {
+        Self {
+            quit: config.quit.clone(),
+            idle_timeout: config.idle_timeout(),
+        }
+    }
+
+    /// Is it a quit message?
+    /// If the `line` is the quit phrase, optionally followed by `"\n"` or `"\r\n"`, return `true`.
+    pub fn is_quit(&self, line: &str) -> bool {
+        let line = line
+            .strip_suffix('\n')
+            .map_or(line, |line| line.strip_suffix('\r').unwrap_or(line));
+        line == self.quit
+    }
+
+    /// When a client active now has been idle for too long, if ever.
+    pub fn idle_deadline(&self) -> Option<Instant> {
+        self.idle_timeout
+            .and_then(|timeout| Instant::now().checked_add(timeout))
+    }
+}
+
+/// Longest line the chat servers and the collector accept from a client, in bytes including the newline.
+pub const MAX_LINE: usize = 64 * 1024;
+
+/// Like [`AsyncBufReadExt::read_until`] a newline, but never let `line` grow beyond [`MAX_LINE`] bytes.
+/// Just as cancel safe: bytes read before cancellation stay in `line`.
+///
+/// # Errors
+/// Returns an error if reading fails, or with [`io::ErrorKind::InvalidData`] if `line` reaches [`MAX_LINE`] bytes without a newline.
+pub(crate) async fn read_bounded_line<Reader>(
+    reader: &mut Reader,
+    line: &mut Vec<u8>,
+) -> io::Result<usize>
+where
+    Reader: AsyncBufRead + Unpin,
+{
+    let limit = MAX_LINE.saturating_sub(line.len()) as u64;
+    let bytes_read = reader.take(limit).read_until(b'\n', line).await?;
+    if line.len() >= MAX_LINE && !line.ends_with(b"\n") {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("Line longer than {MAX_LINE} bytes"),
+        ));
+    }
+    Ok(bytes_read)
+}
+
+/// Wait until `deadline`, forever if there is none.
+pub(crate) async fn sleep_until(deadline: Option<Instant>) {
+    match deadline {
+        Some(deadline) => tokio::time::sleep_until(deadline).await,
+        None => std::future::pending().await,
+    }
+}
+
+impl Default for Session {
+    fn default() -> Self {
+        Self::from_config(&ServerConfig::default())
+    }
 }
 
 /// Broadcast messages sent from one client to all other clients using a [`tokio::sync::broadcast`] channel.
 pub mod chat;
 
 /// Broadcast messages sent from one client to all other clients using a [`tokio::sync::broadcast`] channel.
-/// Additionally, periodically announce the uptime via a [`tokio::sync::watch`] channel.
+/// Additionally, make scheduled announcements (by default the uptime and [`stats`]) via a [`tokio::sync::watch`] channel.
 pub mod chat_with_announce;
 
 /// Broadcast messages sent from one client to all other clients using a [`tokio::sync::broadcast`] channel.
-/// Additionally, commonly share a [`tokio_util::sync::CancellationToken`].
-/// Cancelling this token shuts down all the clients (flushing their readers) and then the entire application.
+/// Additionally, commonly share a root [`tokio_util::sync::CancellationToken`], with a child token per client.
+/// Cancelling the root token shuts down all the clients (flushing their readers) and then the entire application,
+/// cancelling a child token only disconnects that client.
 pub mod chat_with_cancel;
 
 /// Collect messages sent from each connected client (via a [`tokio::sync::mpsc`] channel) and store them in a hashmap.
@@ -40,5 +126,27 @@ pub mod chat_with_cancel;
 /// the serialized hashmap.
 pub mod collector;
 
+/// Connect `stdin` and `stdout` to a server.
+pub mod client;
+
 /// Forward messages sent on reader to writer.
 pub mod echo;
+
+/// A message of the day, sent to clients of the chat servers on connect,
+/// and re-read whenever its file changes (watched with [`notify`]).
+pub mod motd;
+
+/// Ready-to-run servers: bind the configured address, accept clients and spawn a task for each one.
+/// Each function returns a [`server::ServerHandle`] to wait for or stop the server.
+pub mod serve;
+
+/// A reusable TCP server, running a connection handler in a task for each client.
+/// Binding, accepting, connection limits, task tracking and shutdown are taken care of.
+pub mod server;
+
+/// Count clients and messages in an actor task, which clients report to via a [`tokio::sync::mpsc`] channel
+/// and query via a [`tokio::sync::oneshot`] channel.
+pub mod stats;
+
+/// Sources of client connections (TCP, Unix sockets, in-memory pipes), each identifying clients by a [`transport::PeerId`].
+pub mod transport;
@@ -0,0 +1,52 @@
+use anyhow::Context;
+use serde::Deserialize;
+use std::{net::SocketAddr, time::Duration};
+use tracing_subscriber::{fmt, layer::SubscriberExt, util::SubscriberInitExt, EnvFilter, Layer};
+
+/// Output format of log events.
+#[derive(clap::ValueEnum, Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LogFormat {
+    /// Human readable, one line per event.
+    #[default]
+    Human,
+    /// One JSON object per event.
+    Json,
+}
+
+/// Initialize logging to `stdout` in the given `format`.
+///
+/// The `filter` uses [`EnvFilter`] syntax (for example `"achat=debug,info"`).
+/// If it is `None`, the `RUST_LOG` environment variable is used, falling back to `"info"`.
+/// If `console` is given, a console layer publishing on that address is added as well.
+///
+/// # Errors
+/// Returns an error if the filter can not be parsed or a global subscriber was already set.
+pub fn init_logging(
+    filter: Option<&str>,
+    format: LogFormat,
+    console: Option<SocketAddr>,
+) -> anyhow::Result<()> {
+    let filter = match filter {
+        Some(filter) => EnvFilter::try_new(filter).context("Invalid log filter")?,
+        None => EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new("info")),
+    };
+
+    let console = console.map(|addr| {
+        console_subscriber::ConsoleLayer::builder()
+            .retention(Duration::from_secs(60))
+            .server_addr(addr)
+            .spawn()
+    });
+
+    let output = match format {
+        LogFormat::Human => fmt::layer().boxed(),
+        LogFormat::Json => fmt::layer().json().boxed(),
+    };
+
+    tracing_subscriber::registry()
+        .with(console)
+        .with(output.with_filter(filter))
+        .try_init()
+        .context("Failed to initialize logging")
+}
@@ -0,0 +1,199 @@
+use anyhow::Context;
+use notify::{RecursiveMode, Watcher};
+use std::path::{Path, PathBuf};
+use tokio::{
+    io::{AsyncWrite, AsyncWriteExt},
+    sync::{mpsc, watch},
+};
+
+/// The message of the day, kept up to date with the file it was read from.
+#[derive(Debug, Clone)]
+pub struct Motd {
+    rx: watch::Receiver<String>,
+}
+
+impl Motd {
+    /// A message of the day which never changes.
+    pub fn fixed(text: impl Into<String>) -> Self {
+        let (_tx, rx) = watch::channel(text.into());
+        Self { rx }
+    }
+
+    /// Read the message of the day from the file at `path`, and read it again whenever the file changes.
+    /// The directory of the file is watched, so editors replacing the file are noticed as well.
+    ///
+    /// # Termination
+    /// Watching stops when this and all clones are dropped.
+    ///
+    /// # Errors
+    /// Returns an error if the file can not be read or watched.
+    pub fn watch(path: impl AsRef<Path>) -> anyhow::Result<Self> {
+        let path = path.as_ref();
+        let text = std::fs::read_to_string(path)
+            .with_context(|| format!("Failed to read MOTD file {}", path.display()))?;
+        let path = std::fs::canonicalize(path)
+            .with_context(|| format!("Failed to resolve MOTD file {}", path.display()))?;
+        let directory = path
+            .parent()
+            .context("MOTD file has no directory")?
+            .to_path_buf();
+
+        let (events_tx, events) = mpsc::unbounded_channel();
+        let mut watcher = notify::recommended_watcher(move |event| {
+            // If the watching task is gone, nobody cares anymore.
+            let _ = events_tx.send(event);
+        })
+        .context("Failed to create MOTD file watcher")?;
+        watcher
+            .watch(&directory, RecursiveMode::NonRecursive)
+            .with_context(|| format!("Failed to watch {}", directory.display()))?;
+
+        let (tx, rx) = watch::channel(text);
+        tokio::spawn(async move {
+            // The watcher stops watching when dropped.
+            let _watcher = watcher;
+            reload(path, events, tx).await;
+        });
+        Ok(Self { rx })
+    }
+
+    /// The current message of the day.
+    pub fn get(&self) -> String {
+        self.rx.borrow().clone()
+    }
+
+    /// A receiver notified of every change of the message of the day.
+    pub fn subscribe(&self) -> watch::Receiver<String> {
+        self.rx.clone()
+    }
+
+    /// Send the current message of the day on `writer`, see [`format()`].
+    ///
+    /// # Errors
+    /// Returns an error if writing fails.
+    pub async fn greet(&self, writer: &mut (impl AsyncWrite + Unpin)) -> anyhow::Result<()> {
+        writer
+            .write_all(format(&self.get()).as_bytes())
+            .await
+            .context("Unable to send MOTD")
+    }
+}
+
+/// Each line of the message of the day `text`, prefixed with `MOTD: `.
+pub fn format(text: &str) -> String {
+    text.lines().map(|line| format!("MOTD: {line}\n")).collect()
+}
+
+/// Whenever `motd` changes, send the new message of the day on `announcements`.
+/// The text is sent as is, it is up to the receivers to mark each line as announced.
+///
+/// # Termination
+/// When the `motd` stops changing or all receivers of `announcements` are dropped, the future terminates.
+pub async fn announce(motd: Motd, announcements: watch::Sender<String>) {
+    let mut rx = motd.subscribe();
+    while rx.changed().await.is_ok() {
+        let text = rx.borrow_and_update().trim_end_matches('\n').to_string();
+        if announcements.send(text).is_err() {
+            // receivers dropped.
+            break;
+        }
+    }
+}
+
+/// On each file system event concerning `path`, read the file again and update `tx` if its content changed.
+async fn reload(
+    path: PathBuf,
+    mut events: mpsc::UnboundedReceiver<notify::Result<notify::Event>>,
+    tx: watch::Sender<String>,
+) {
+    loop {
+        let event = tokio::select! {
+            () = tx.closed() => break,
+            event = events.recv() => match event {
+                Some(event) => event,
+                None => break,
+            },
+        };
+        match event {
+            Ok(event) if event.paths.contains(&path) => {}
+            Ok(_) => continue,
+            Err(error) => {
+                tracing::warn!(?error, "Failed to watch MOTD file");
+                continue;
+            }
+        }
+        match tokio::fs::read_to_string(&path).await {
+            Ok(text) => {
+                let changed = tx.send_if_modified(|current| {
+                    if *current == text {
+                        return false;
+                    }
+                    *current = text;
+                    true
+                });
+                if changed {
+                    tracing::info!(path = %path.display(), "Reloaded MOTD");
+                }
+            }
+            // Editors may remove the file before writing it again, so keep the last one.
+            Err(error) => tracing::debug!(?error, "Failed to read MOTD file"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::time::Duration;
+    use tokio_test::io::Builder as Mock;
+
+    #[test]
+    fn formats_each_line() {
+        assert_eq!(format("Hello\nWorld\n"), "MOTD: Hello\nMOTD: World\n");
+        assert_eq!(format(""), "");
+    }
+
+    #[tokio::test]
+    async fn greets_with_motd() {
+        let mut writer = Mock::new().write(b"MOTD: Welcome\n").build();
+        Motd::fixed("Welcome\n").greet(&mut writer).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn reloads_changed_file() {
+        let directory = std::env::temp_dir().join(format!("achat-motd-{}", std::process::id()));
+        std::fs::create_dir_all(&directory).unwrap();
+        let path = directory.join("motd.txt");
+        std::fs::write(&path, "Old news").unwrap();
+
+        let motd = Motd::watch(&path).unwrap();
+        assert_eq!(motd.get(), "Old news");
+
+        let mut rx = motd.subscribe();
+        std::fs::write(&path, "Fresh news").unwrap();
+        // Writing may be noticed halfway, with the file still empty.
+        tokio::time::timeout(
+            Duration::from_secs(5),
+            rx.wait_for(|text| text == "Fresh news"),
+        )
+        .await
+        .unwrap()
+        .unwrap();
+
+        std::fs::remove_dir_all(&directory).unwrap();
+    }
+
+    #[tokio::test]
+    async fn announces_changes() {
+        let (tx, rx) = watch::channel("Old news".to_string());
+        let (announce_tx, mut announcements) = watch::channel(String::new());
+        let handle = tokio::spawn(announce(Motd { rx }, announce_tx));
+
+        tx.send("Fresh\nnews\n".to_string()).unwrap();
+        announcements.changed().await.unwrap();
+        assert_eq!(*announcements.borrow(), "Fresh\nnews");
+
+        drop(tx);
+        handle.await.unwrap();
+    }
+}
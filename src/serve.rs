@@ -0,0 +1,373 @@
+use anyhow::Context;
+use std::{path::PathBuf, str};
+use tokio::{
+    io::AsyncReadExt,
+    sync::{broadcast, mpsc, watch},
+    task::JoinHandle,
+};
+use tokio_util::sync::CancellationToken;
+
+use crate::{
+    chat,
+    chat_with_announce::{self, Announcement, Topic},
+    chat_with_cancel::{self, Registry, Termination},
+    collector::{self, Collection},
+    echo,
+    motd::{self, Motd},
+    server::{Server, ServerHandle},
+    stats,
+    transport::PeerId,
+    Config, Session,
+};
+
+/// The available servers.
+#[derive(clap::ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Kind {
+    /// Broadcast messages to all other clients, see [`chat()`].
+    Chat,
+    /// Send messages back to their sender, see [`echo()`].
+    Echo,
+    /// Collect messages and report them on request, see [`collector()`].
+    Collector,
+    /// Dump received messages on `stdout`, see [`dump()`].
+    Dump,
+    /// Chat with scheduled announcements, by default of the uptime and statistics, see [`chat_with_announce()`].
+    Announce,
+    /// Chat, terminated by a client sending `call it a day` and the configured secret, see [`chat_with_cancel()`].
+    Cancel,
+}
+
+/// Start the server of the given `kind`.
+///
+/// # Errors
+/// Returns an error if binding fails.
+pub async fn start(kind: Kind, config: &Config) -> anyhow::Result<ServerHandle> {
+    match kind {
+        Kind::Chat => chat(config).await,
+        Kind::Echo => echo(config).await,
+        Kind::Collector => collector(config).await,
+        Kind::Dump => dump(config).await,
+        Kind::Announce => chat_with_announce(config).await,
+        Kind::Cancel => chat_with_cancel(config).await,
+    }
+}
+
+/// Start the [`echo`] server.
+///
+/// # Errors
+/// Returns an error if binding fails.
+pub async fn echo(config: &Config) -> anyhow::Result<ServerHandle> {
+    Server::from_config(&config.server)
+        .serve(config.server.address, |mut socket, _peer| async move {
+            let (reader, writer) = socket.split();
+            echo::handle_connection(reader, writer).await
+        })
+        .await
+}
+
+/// Start the [`collector`] server.
+/// If a snapshot file is configured, restore the collected messages from it, and keep it up to date.
+/// Messages are evicted according to the configured retention.
+/// If an HTTP address is configured, also serve the [`collector::http`] API on it.
+/// Joining the server waits for the final snapshot.
+///
+/// # Errors
+/// Returns an error if the snapshot can not be restored or binding fails.
+pub async fn collector(config: &Config) -> anyhow::Result<ServerHandle> {
+    let (tx, rx) = mpsc::channel(config.server.capacity);
+    let snapshots = config.collector.snapshots();
+    let collection = match &snapshots {
+        Some(snapshots) => {
+            let collection = Collection::load(&snapshots.path)?;
+            tracing::info!(
+                messages = collection.len(),
+                path = %snapshots.path.display(),
+                "Restored collection"
+            );
+            collection
+        }
+        None => Collection::default(),
+    };
+
+    let collector = tokio::spawn(collector::collect_with(
+        rx,
+        collection,
+        snapshots,
+        config.collector.retention(),
+    ));
+
+    let http_tx = tx.clone();
+    let handle = Server::from_config(&config.server)
+        .serve(config.server.address, move |mut socket, peer| {
+            let tx = tx.clone();
+            async move {
+                let (reader, writer) = socket.split();
+                collector::handle_connection(peer, reader, writer, tx).await
+            }
+        })
+        .await?
+        .attach(collector);
+
+    let Some(address) = config.collector.http else {
+        return Ok(handle);
+    };
+    match collector::http::serve(address, http_tx, handle.token()) {
+        Ok((_address, http)) => Ok(handle.attach(http)),
+        Err(error) => {
+            handle.shutdown();
+            Err(error)
+        }
+    }
+}
+
+/// Start the [`chat`] server.
+///
+/// # Errors
+/// Returns an error if binding fails or the MOTD file can not be read.
+pub async fn chat(config: &Config) -> anyhow::Result<ServerHandle> {
+    let (tx, _rx) = broadcast::channel(config.server.capacity);
+    let session = Session::from_config(&config.server);
+    let motd = motd_of(config)?;
+
+    Server::from_config(&config.server)
+        .serve(config.server.address, move |mut socket, peer| {
+            let tx = tx.clone();
+            let rx = tx.subscribe();
+            let session = session.clone();
+            let motd = motd.clone();
+            async move {
+                if let Some(motd) = motd {
+                    motd.greet(&mut socket).await?;
+                }
+                let (reader, writer) = socket.split();
+                chat::handle_connection(peer, reader, writer, tx, rx, session).await
+            }
+        })
+        .await
+}
+
+/// Start the [`chat_with_announce`] server.
+/// If the configuration was read from a file, the announcements are reloaded from it on `SIGHUP`.
+///
+/// # Errors
+/// Returns an error if binding fails, the announcements or topic setters are invalid, the MOTD file can not be read
+/// or `SIGHUP` can not be handled.
+pub async fn chat_with_announce(config: &Config) -> anyhow::Result<ServerHandle> {
+    let (schedule_tx, schedule) = watch::channel(config.chat_with_announce.announcements()?);
+    let handle = chat_with_announce_in(config, schedule).await?;
+    let Some(path) = &config.path else {
+        return Ok(handle);
+    };
+    match reload_on_hangup(path.clone(), schedule_tx, handle.token()) {
+        Ok(reload) => Ok(handle.attach(reload)),
+        Err(error) => {
+            handle.shutdown();
+            Err(error)
+        }
+    }
+}
+
+/// Re-read the configuration file at `path` and reschedule the announcements on `SIGHUP`, until `token` is cancelled.
+#[cfg(unix)]
+fn reload_on_hangup(
+    path: PathBuf,
+    schedule: watch::Sender<Vec<Announcement>>,
+    token: CancellationToken,
+) -> anyhow::Result<JoinHandle<anyhow::Result<()>>> {
+    use tokio::signal::unix::{signal, SignalKind};
+
+    let mut hangup = signal(SignalKind::hangup()).context("Failed to handle SIGHUP")?;
+    Ok(tokio::spawn(async move {
+        loop {
+            tokio::select! {
+                _ = token.cancelled() => break Ok(()),
+                Some(()) = hangup.recv() => {
+                    match Config::from_file(&path).and_then(|config| config.chat_with_announce.announcements()) {
+                        Ok(announcements) => {
+                            tracing::info!(path = %path.display(), "Reloaded announcements");
+                            schedule.send_replace(announcements);
+                        }
+                        Err(error) => tracing::warn!(?error, "Keeping announcements, reload failed"),
+                    }
+                }
+                else => break Ok(()),
+            }
+        }
+    }))
+}
+
+#[cfg(not(unix))]
+fn reload_on_hangup(
+    _path: PathBuf,
+    _schedule: watch::Sender<Vec<Announcement>>,
+    _token: CancellationToken,
+) -> anyhow::Result<JoinHandle<anyhow::Result<()>>> {
+    Ok(tokio::spawn(async { Ok(()) }))
+}
+
+/// Like [`chat_with_announce()`], but make the announcements in `schedule`,
+/// starting over whenever it changes, until the server shuts down.
+/// Changes of the MOTD file are announced as well.
+///
+/// # Errors
+/// Returns an error if binding fails, the topic setters are invalid or the MOTD file can not be read.
+pub async fn chat_with_announce_in(
+    config: &Config,
+    schedule: watch::Receiver<Vec<Announcement>>,
+) -> anyhow::Result<ServerHandle> {
+    let (tx, _rx) = broadcast::channel(config.server.capacity);
+    let announce = &config.chat_with_announce;
+    let topic = Topic::new(announce.topic.clone(), announce.topic_setters()?);
+    let (announce_tx, announcements) = watch::channel(String::new());
+    let (statistics, stats_rx) = stats::channel();
+    let session = Session::from_config(&config.server);
+    let motd = motd_of(config)?;
+
+    // Finishes once the server, its clients and the scheduler are done with the statistics.
+    let stats = tokio::spawn(stats::collect(stats_rx));
+    let announcer = motd
+        .clone()
+        .map(|motd| motd::announce(motd, announce_tx.clone()));
+    let scheduler = chat_with_announce::schedule_announcements(
+        schedule,
+        announce_tx,
+        statistics.clone(),
+        chrono::Utc::now(),
+    );
+
+    let handle = Server::from_config(&config.server)
+        .serve(config.server.address, move |mut socket, peer| {
+            let tx = tx.clone();
+            let rx = tx.subscribe();
+            let topic = topic.clone();
+            let announcements = announcements.clone();
+            let statistics = statistics.clone();
+            let session = session.clone();
+            let motd = motd.clone();
+            async move {
+                if let Some(motd) = motd {
+                    motd.greet(&mut socket).await?;
+                }
+                let (reader, writer) = socket.split();
+                chat_with_announce::handle_connection(
+                    peer,
+                    reader,
+                    writer,
+                    tx,
+                    rx,
+                    topic,
+                    announcements,
+                    statistics,
+                    session,
+                )
+                .await
+            }
+        })
+        .await?;
+
+    // Announcers only notice that all clients are gone when announcing next, so stop them with the server.
+    let token = handle.token();
+    let scheduler = tokio::spawn(async move {
+        token
+            .run_until_cancelled(scheduler)
+            .await
+            .unwrap_or(Ok(()))
+            .context("Failed to make announcements")
+    });
+    let handle = handle.attach(stats).attach(scheduler);
+    let Some(announcer) = announcer else {
+        return Ok(handle);
+    };
+    let token = handle.token();
+    let announcer = tokio::spawn(async move {
+        token.run_until_cancelled(announcer).await;
+        Ok(())
+    });
+    Ok(handle.attach(announcer))
+}
+
+/// Start the [`chat_with_cancel`] server.
+///
+/// # Termination
+/// When a client sends the configured termination phrase and secret, the server stops accepting and tells all clients it is shutting down.
+/// Clients still connected after the configured grace period are aborted.
+///
+/// # Errors
+/// Returns an error if binding fails or the MOTD file can not be read.
+pub async fn chat_with_cancel(config: &Config) -> anyhow::Result<ServerHandle> {
+    chat_with_cancel_in(config, Registry::default()).await
+}
+
+/// Like [`chat_with_cancel()`], but register clients in `registry`,
+/// through which each of them can be cancelled individually.
+/// Cancelling the root token of the `registry` shuts down the server.
+///
+/// # Errors
+/// Returns an error if binding fails or the MOTD file can not be read.
+pub async fn chat_with_cancel_in(
+    config: &Config,
+    registry: Registry<PeerId>,
+) -> anyhow::Result<ServerHandle> {
+    let (tx, _rx) = broadcast::channel(config.server.capacity);
+    let termination = Termination::from_config(config);
+    let session = Session::from_config(&config.server);
+    let motd = motd_of(config)?;
+
+    Server::from_config(&config.server)
+        .token(registry.token())
+        .serve(config.server.address, move |mut socket, peer| {
+            let tx = tx.clone();
+            let rx = tx.subscribe();
+            let registry = registry.clone();
+            let termination = termination.clone();
+            let session = session.clone();
+            let motd = motd.clone();
+            async move {
+                if let Some(motd) = motd {
+                    motd.greet(&mut socket).await?;
+                }
+                let (reader, writer) = socket.split();
+                chat_with_cancel::handle_connection(
+                    peer,
+                    reader,
+                    writer,
+                    tx,
+                    rx,
+                    registry,
+                    termination,
+                    session,
+                )
+                .await
+            }
+        })
+        .await
+}
+
+/// The message of the day of the chat servers, if a MOTD file is configured.
+fn motd_of(config: &Config) -> anyhow::Result<Option<Motd>> {
+    config.server.motd.as_ref().map(Motd::watch).transpose()
+}
+
+/// Start a server which dumps whatever clients send on `stdout`.
+///
+/// # Errors
+/// Returns an error if binding fails.
+pub async fn dump(config: &Config) -> anyhow::Result<ServerHandle> {
+    Server::from_config(&config.server)
+        .serve(config.server.address, |mut socket, _peer| async move {
+            let (mut reader, _) = socket.split();
+            let mut buffer = [0; 1024];
+            loop {
+                let n = reader.read(&mut buffer).await?;
+                if n == 0 {
+                    tracing::info!("Client disconnected");
+                    break Ok(());
+                }
+                match str::from_utf8(&buffer[..n]) {
+                    Ok(s) => println!("Received {n} bytes: {s:#?}"),
+                    Err(e) => println!("Received {n} bytes of wrong UTF8 data: {e:#?}"),
+                }
+            }
+        })
+        .await
+}
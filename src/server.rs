@@ -0,0 +1,580 @@
+use anyhow::Context;
+use std::{collections::HashMap, future::Future, net::SocketAddr, sync::Arc, time::Duration};
+use tokio::{
+    net::{TcpListener, TcpStream},
+    sync::Semaphore,
+    task::{JoinHandle, JoinSet},
+};
+use tokio_util::sync::CancellationToken;
+use tracing::Instrument;
+
+use crate::{
+    transport::{Acceptor, PeerId},
+    ServerConfig,
+};
+
+/// Builder for a server running a connection handler for each accepted client.
+///
+/// ```no_run
+/// # async fn run() -> anyhow::Result<()> {
+/// use achat::{echo, server::Server};
+///
+/// let server = Server::new()
+///     .max_connections(100)
+///     .serve("127.0.0.1:0".parse()?, |mut socket, _peer| async move {
+///         let (reader, writer) = socket.split();
+///         echo::handle_connection(reader, writer).await
+///     })
+///     .await?;
+/// println!("Listening on {}", server.local_addr());
+/// server.stop().await
+/// # }
+/// ```
+#[derive(Debug)]
+pub struct Server {
+    max_connections: Option<usize>,
+    grace_period: Duration,
+    token: CancellationToken,
+}
+
+impl Default for Server {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Server {
+    /// Server without a connection limit.
+    pub fn new() -> Self {
+        Self {
+            max_connections: None,
+            grace_period: Duration::from_secs(5),
+            token: CancellationToken::new(),
+        }
+    }
+
+    /// Server with the configured connection limit and grace period.
+    /// The configured address is passed to [`Self::serve`].
+    pub fn from_config(config: &ServerConfig) -> Self {
+        let server = Self::new().grace_period(config.grace_period());
+        match config.max_connections {
+            Some(max) => server.max_connections(max),
+            None => server,
+        }
+    }
+
+    /// Accept at most `max` clients at the same time. Further clients wait in the listen backlog.
+    pub fn max_connections(mut self, max: usize) -> Self {
+        self.max_connections = Some(max);
+        self
+    }
+
+    /// After shutdown, give connections this long to finish before aborting them (after another [`ABORT_MARGIN`]).
+    pub fn grace_period(mut self, grace_period: Duration) -> Self {
+        self.grace_period = grace_period;
+        self
+    }
+
+    /// Shut down the server when `token` is cancelled.
+    /// Connection handlers may watch the same token to finish early.
+    pub fn token(mut self, token: CancellationToken) -> Self {
+        self.token = token;
+        self
+    }
+
+    /// Bind a TCP listener on `address` and start accepting clients in a background task.
+    /// Each client is handled by the future returned from `handler`, running in its own task.
+    ///
+    /// # Errors
+    /// Returns an error if binding fails.
+    pub async fn serve<Handler, Fut>(
+        self,
+        address: SocketAddr,
+        handler: Handler,
+    ) -> anyhow::Result<ServerHandle>
+    where
+        Handler: Fn(TcpStream, PeerId) -> Fut + Send + 'static,
+        Fut: Future<Output = anyhow::Result<()>> + Send + 'static,
+    {
+        let listener = TcpListener::bind(&address)
+            .await
+            .with_context(|| format!("Failed to bind on {address}"))?;
+        self.serve_on(listener, handler)
+    }
+
+    /// Like [`Self::serve`], but accept clients from `acceptor` instead of binding a TCP address.
+    ///
+    /// # Errors
+    /// Returns an error if the local address of `acceptor` can not be determined.
+    pub fn serve_on<A, Handler, Fut>(
+        self,
+        acceptor: A,
+        handler: Handler,
+    ) -> anyhow::Result<ServerHandle<A::Addr>>
+    where
+        A: Acceptor,
+        Handler: Fn(A::Stream, PeerId) -> Fut + Send + 'static,
+        Fut: Future<Output = anyhow::Result<()>> + Send + 'static,
+    {
+        let local_addr = acceptor
+            .local_addr()
+            .context("Failed to get local address")?;
+        tracing::info!(address = ?local_addr, "Listening");
+
+        let token = self.token.clone();
+        let task = tokio::spawn(self.accept_loop(acceptor, handler));
+        Ok(ServerHandle {
+            local_addr,
+            token,
+            task,
+            attached: Vec::new(),
+        })
+    }
+
+    async fn accept_loop<A, Handler, Fut>(self, mut acceptor: A, handler: Handler) -> Shutdown
+    where
+        A: Acceptor,
+        Handler: Fn(A::Stream, PeerId) -> Fut + Send + 'static,
+        Fut: Future<Output = anyhow::Result<()>> + Send + 'static,
+    {
+        let limit = self
+            .max_connections
+            .map(|max| Arc::new(Semaphore::new(max)));
+        let mut clients = Clients::default();
+
+        loop {
+            let permit = match &limit {
+                Some(limit) => tokio::select! {
+                    _ = self.token.cancelled() => break,
+                    Some(result) = clients.tasks.join_next_with_id(), if !clients.tasks.is_empty() => {
+                        clients.reap(result);
+                        continue;
+                    }
+                    permit = limit.clone().acquire_owned() => Some(permit.expect("Semaphore is never closed")),
+                },
+                None => None,
+            };
+
+            tokio::select! {
+                _ = self.token.cancelled() => break,
+                Some(result) = clients.tasks.join_next_with_id(), if !clients.tasks.is_empty() => {
+                    clients.reap(result);
+                }
+                accepted = acceptor.accept() => match accepted {
+                    Ok((stream, peer)) => {
+                        tracing::info!(%peer, "Received connection");
+                        let connection = handler(stream, peer);
+                        let handle = clients.tasks.spawn(
+                            async move {
+                                let result = connection.await;
+                                drop(permit);
+                                result
+                            }
+                            .instrument(tracing::info_span!("client", %peer)),
+                        );
+                        clients.peers.insert(handle.id(), peer);
+                    }
+                    Err(e) => {
+                        tracing::warn!("Failed to accept connection: {e}");
+                        // Avoid spinning if accepting fails repeatedly, e.g. when out of file descriptors.
+                        tokio::select! {
+                            _ = self.token.cancelled() => break,
+                            () = tokio::time::sleep(Duration::from_millis(100)) => {}
+                        }
+                    }
+                },
+            }
+        }
+
+        drop(acceptor);
+        tracing::info!(clients = clients.tasks.len(), "Shutting down");
+        let drained = tokio::time::timeout(self.grace_period.saturating_add(ABORT_MARGIN), async {
+            while let Some(result) = clients.tasks.join_next_with_id().await {
+                clients.reap(result);
+            }
+        })
+        .await;
+        if drained.is_err() {
+            for peer in clients.peers.values() {
+                tracing::warn!(%peer, "Aborting client after grace period");
+            }
+            clients.report.aborted = clients.peers.drain().map(|(_, peer)| peer).collect();
+            clients.tasks.shutdown().await;
+        }
+
+        let report = clients.report;
+        if report.failed > 0 {
+            tracing::warn!(
+                finished = report.finished,
+                failed = report.failed,
+                aborted = report.aborted.len(),
+                "Shut down, some clients failed"
+            );
+            for failure in &report.failures {
+                tracing::warn!(peer = %failure.peer, "{}", failure.outcome);
+            }
+        } else {
+            tracing::info!(
+                finished = report.finished,
+                aborted = report.aborted.len(),
+                "Shut down"
+            );
+        }
+        report
+    }
+}
+
+/// Time connections still running when the grace period ends get before they are aborted,
+/// so handlers which finish right at the end of the grace period are not raced by the abort.
+pub const ABORT_MARGIN: Duration = Duration::from_secs(1);
+
+/// Number of failures kept in a [`Shutdown`] report, so a long-running server does not accumulate them forever.
+pub const MAX_REPORTED_FAILURES: usize = 100;
+
+/// What happened to the clients of a [`Server`], reported when it shut down.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct Shutdown {
+    /// Number of connections whose handler finished successfully.
+    pub finished: usize,
+    /// Number of connections whose handler returned an error or panicked.
+    pub failed: usize,
+    /// The first [`MAX_REPORTED_FAILURES`] failed connections.
+    pub failures: Vec<Failure>,
+    /// Clients which did not finish within the grace period and were force-closed.
+    pub aborted: Vec<PeerId>,
+}
+
+/// A connection whose handler did not finish successfully.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Failure {
+    /// The client served by the connection.
+    pub peer: PeerId,
+    /// How the connection handler ended.
+    pub outcome: Outcome,
+}
+
+/// How a connection handler ended.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Outcome {
+    /// The handler returned `Ok`.
+    Finished,
+    /// The handler returned an error, with this message.
+    Failed(String),
+    /// The handler panicked, with this message.
+    Panicked(String),
+}
+
+impl std::fmt::Display for Outcome {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Finished => write!(f, "Client finished"),
+            Self::Failed(e) => write!(f, "Failed to handle connection: {e}"),
+            Self::Panicked(e) => write!(f, "Client task panicked: {e}"),
+        }
+    }
+}
+
+/// Connection tasks, together with the peer each of them is serving.
+#[derive(Default)]
+struct Clients {
+    tasks: JoinSet<anyhow::Result<()>>,
+    peers: HashMap<tokio::task::Id, PeerId>,
+    report: Shutdown,
+}
+
+impl Clients {
+    /// Forget a finished connection task, log how it ended and record it in the report.
+    fn reap(
+        &mut self,
+        result: Result<(tokio::task::Id, anyhow::Result<()>), tokio::task::JoinError>,
+    ) {
+        let (id, outcome) = match result {
+            Ok((id, Ok(()))) => (id, Outcome::Finished),
+            Ok((id, Err(e))) => (id, Outcome::Failed(format!("{e:#}"))),
+            Err(e) => (e.id(), Outcome::Panicked(panic_message(e))),
+        };
+        let peer = self
+            .peers
+            .remove(&id)
+            .expect("Every connection task has a peer");
+        match &outcome {
+            Outcome::Finished => {
+                tracing::debug!(%peer, "{outcome}");
+                self.report.finished += 1;
+                return;
+            }
+            Outcome::Failed(_) => tracing::warn!(%peer, "{outcome}"),
+            Outcome::Panicked(_) => tracing::error!(%peer, "{outcome}"),
+        }
+        self.report.failed += 1;
+        if self.report.failures.len() < MAX_REPORTED_FAILURES {
+            self.report.failures.push(Failure { peer, outcome });
+        }
+    }
+}
+
+/// The message a task panicked with, if it was a string.
+fn panic_message(error: tokio::task::JoinError) -> String {
+    match error.try_into_panic() {
+        Ok(payload) => payload
+            .downcast_ref::<&str>()
+            .map(ToString::to_string)
+            .or_else(|| payload.downcast_ref::<String>().cloned())
+            .unwrap_or_else(|| "Unknown panic".to_string()),
+        Err(e) => e.to_string(),
+    }
+}
+
+/// Handle to a running [`Server`].
+#[derive(Debug)]
+pub struct ServerHandle<Addr = SocketAddr> {
+    local_addr: Addr,
+    token: CancellationToken,
+    task: JoinHandle<Shutdown>,
+    attached: Vec<JoinHandle<anyhow::Result<()>>>,
+}
+
+impl<Addr: Clone> ServerHandle<Addr> {
+    /// The address the server is reachable on. Useful when binding to port `0`.
+    pub fn local_addr(&self) -> Addr {
+        self.local_addr.clone()
+    }
+
+    /// The token which shuts down the server when cancelled.
+    pub fn token(&self) -> CancellationToken {
+        self.token.clone()
+    }
+
+    /// When joining, also wait for `task` after the connections have finished.
+    /// Meant for tasks serving the connections, which finish once all of them are gone.
+    pub fn attach(mut self, task: JoinHandle<anyhow::Result<()>>) -> Self {
+        self.attached.push(task);
+        self
+    }
+
+    /// [`Self::shutdown`] the server when the process receives Ctrl-C.
+    pub fn shutdown_on_ctrl_c(self) -> Self {
+        let token = self.token.clone();
+        tokio::spawn(async move {
+            tokio::select! {
+                _ = token.cancelled() => {}
+                result = tokio::signal::ctrl_c() => match result {
+                    Ok(()) => {
+                        tracing::info!("Interrupted, shutting down");
+                        token.cancel();
+                    }
+                    Err(e) => tracing::warn!("Failed to listen for Ctrl-C: {e}"),
+                },
+            }
+        });
+        self
+    }
+
+    /// Stop accepting clients and shut down the connections, without waiting for it.
+    pub fn shutdown(&self) {
+        self.token.cancel();
+    }
+
+    /// Wait until the server has shut down and all its connections have finished.
+    ///
+    /// # Errors
+    /// Returns an error if the accept loop panicked, or an [attached](Self::attach) task failed.
+    pub async fn join(self) -> anyhow::Result<()> {
+        self.join_report().await.map(drop)
+    }
+
+    /// Like [`Self::join`], but return what happened during shutdown.
+    ///
+    /// # Errors
+    /// Returns an error if the accept loop panicked, or an [attached](Self::attach) task failed.
+    pub async fn join_report(self) -> anyhow::Result<Shutdown> {
+        let shutdown = self.task.await.context("Accept loop failed")?;
+        for task in self.attached {
+            task.await.context("Attached task panicked")??;
+        }
+        Ok(shutdown)
+    }
+
+    /// [`Self::shutdown`] the server, then [`Self::join`] it.
+    ///
+    /// # Errors
+    /// Returns an error if the accept loop panicked, or an [attached](Self::attach) task failed.
+    pub async fn stop(self) -> anyhow::Result<()> {
+        self.shutdown();
+        self.join().await
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::echo;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    async fn echo_server(server: Server) -> ServerHandle {
+        server
+            .serve(
+                "127.0.0.1:0".parse().unwrap(),
+                |mut socket, _peer| async move {
+                    let (reader, writer) = socket.split();
+                    echo::handle_connection(reader, writer).await
+                },
+            )
+            .await
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn serves_clients() {
+        let server = echo_server(Server::new()).await;
+
+        let mut client = TcpStream::connect(server.local_addr()).await.unwrap();
+        client.write_all(b"hello").await.unwrap();
+        let mut buffer = [0; 5];
+        client.read_exact(&mut buffer).await.unwrap();
+        assert_eq!(&buffer, b"hello");
+
+        drop(client);
+        server.stop().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn limits_connections() {
+        let server = echo_server(Server::new().max_connections(1)).await;
+
+        let mut first = TcpStream::connect(server.local_addr()).await.unwrap();
+        let mut second = TcpStream::connect(server.local_addr()).await.unwrap();
+        first.write_all(b"1").await.unwrap();
+        second.write_all(b"2").await.unwrap();
+
+        let mut buffer = [0; 1];
+        first.read_exact(&mut buffer).await.unwrap();
+        assert_eq!(&buffer, b"1");
+        let waiting =
+            tokio::time::timeout(Duration::from_millis(100), second.read_exact(&mut buffer)).await;
+        assert!(waiting.is_err(), "second client must not be served yet");
+
+        drop(first);
+        second.read_exact(&mut buffer).await.unwrap();
+        assert_eq!(&buffer, b"2");
+
+        drop(second);
+        server.stop().await.unwrap();
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn aborts_clients_after_grace_period() {
+        let server = echo_server(Server::new().grace_period(Duration::from_secs(1))).await;
+
+        let mut client = TcpStream::connect(server.local_addr()).await.unwrap();
+        client.write_all(b"hi").await.unwrap();
+        let mut buffer = [0; 2];
+        client.read_exact(&mut buffer).await.unwrap();
+
+        server.shutdown();
+        let shutdown = server.join_report().await.unwrap();
+        assert_eq!(shutdown.aborted, vec![client.local_addr().unwrap().into()]);
+        assert_eq!(client.read(&mut buffer).await.unwrap(), 0);
+    }
+
+    #[tokio::test]
+    async fn reports_client_outcomes() {
+        let server = Server::new()
+            .serve(
+                "127.0.0.1:0".parse().unwrap(),
+                |mut socket, _peer| async move {
+                    let mut command = [0; 1];
+                    socket.read_exact(&mut command).await?;
+                    match &command {
+                        b"o" => Ok(()),
+                        b"e" => anyhow::bail!("Bad command"),
+                        _ => panic!("Unexpected command"),
+                    }
+                },
+            )
+            .await
+            .unwrap();
+
+        let mut failures = Vec::new();
+        for command in [b"o", b"e", b"p", b"o"] {
+            let mut client = TcpStream::connect(server.local_addr()).await.unwrap();
+            client.write_all(command).await.unwrap();
+            // The server closes the connection once the handler is done.
+            assert_eq!(client.read(&mut [0; 1]).await.unwrap(), 0);
+            if command != b"o" {
+                failures.push(client.local_addr().unwrap().into());
+            }
+        }
+
+        server.shutdown();
+        let shutdown = server.join_report().await.unwrap();
+        assert_eq!(shutdown.finished, 2);
+        assert_eq!(shutdown.failed, 2);
+        assert_eq!(
+            shutdown.failures,
+            vec![
+                Failure {
+                    peer: failures[0],
+                    outcome: Outcome::Failed("Bad command".to_string()),
+                },
+                Failure {
+                    peer: failures[1],
+                    outcome: Outcome::Panicked("Unexpected command".to_string()),
+                },
+            ]
+        );
+        assert!(shutdown.aborted.is_empty());
+    }
+
+    #[tokio::test]
+    async fn serves_any_acceptor() {
+        let (addr, acceptor) = crate::transport::memory(64);
+        let server = Server::new()
+            .serve_on(acceptor, |stream, _peer| async move {
+                let (reader, writer) = tokio::io::split(stream);
+                echo::handle_connection(reader, writer).await
+            })
+            .unwrap();
+
+        let mut client = server.local_addr().connect().await.unwrap();
+        client.write_all(b"hello").await.unwrap();
+        let mut buffer = [0; 5];
+        client.read_exact(&mut buffer).await.unwrap();
+        assert_eq!(&buffer, b"hello");
+
+        drop((addr, client));
+        server.stop().await.unwrap();
+    }
+
+    /// Fails to accept every client, notifying each time.
+    struct FailingAcceptor(Arc<tokio::sync::Notify>);
+
+    impl Acceptor for FailingAcceptor {
+        type Stream = TcpStream;
+        type Addr = ();
+
+        async fn accept(&mut self) -> std::io::Result<(Self::Stream, PeerId)> {
+            self.0.notify_one();
+            Err(std::io::Error::other("Too many open files"))
+        }
+
+        fn local_addr(&self) -> std::io::Result<Self::Addr> {
+            Ok(())
+        }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn shuts_down_while_backing_off_from_accept_errors() {
+        let failed = Arc::new(tokio::sync::Notify::new());
+        let server = Server::new()
+            .serve_on(FailingAcceptor(failed.clone()), |_stream, _peer| async {
+                Ok(())
+            })
+            .unwrap();
+        failed.notified().await;
+
+        let started = tokio::time::Instant::now();
+        server.stop().await.unwrap();
+        assert!(started.elapsed() < Duration::from_millis(100));
+    }
+}
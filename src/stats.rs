@@ -0,0 +1,233 @@
+use anyhow::Context;
+use std::{
+    collections::{HashMap, VecDeque},
+    fmt::{self, Display},
+    time::Duration,
+};
+use tokio::{
+    sync::{mpsc, oneshot},
+    time::Instant,
+};
+
+/// Period over which the message rate and the busiest sender are computed.
+const WINDOW: Duration = Duration::from_secs(60);
+
+/// An event reported to the statistics actor, see [`collect()`].
+#[derive(Debug)]
+pub enum Event {
+    /// A client connected.
+    Connected,
+
+    /// A client disconnected.
+    Disconnected,
+
+    /// A client sent a message.
+    Message {
+        /// Origin of message.
+        sender: String,
+    },
+
+    /// Request for the current statistics.
+    Query {
+        /// A oneshot channel for sending the reply.
+        reply: oneshot::Sender<Stats>,
+    },
+}
+
+/// Create a handle for reporting to the statistics actor, and the receiver to run it on with [`collect()`].
+///
+/// The channel is unbounded, so reporting never waits and a disconnect can be reported on drop.
+pub fn channel() -> (Statistics, mpsc::UnboundedReceiver<Event>) {
+    let (tx, rx) = mpsc::unbounded_channel();
+    (Statistics { tx }, rx)
+}
+
+/// Handle to the statistics actor, shared by all clients.
+#[derive(Debug, Clone)]
+pub struct Statistics {
+    tx: mpsc::UnboundedSender<Event>,
+}
+
+impl Statistics {
+    /// Report a connected client, until the returned [`Connection`] is dropped.
+    pub fn connect(&self) -> Connection {
+        // If the actor is gone, there is nobody left to count.
+        let _ = self.tx.send(Event::Connected);
+        Connection {
+            tx: self.tx.clone(),
+        }
+    }
+
+    /// Fetch the current statistics.
+    ///
+    /// # Errors
+    /// Returns an error if the actor is gone.
+    pub async fn query(&self) -> anyhow::Result<Stats> {
+        let (reply, receiver) = oneshot::channel();
+        self.tx
+            .send(Event::Query { reply })
+            .context("Statistics are gone")?;
+        receiver.await.context("Failed to fetch statistics")
+    }
+}
+
+/// A connected client, reported as disconnected when dropped.
+#[derive(Debug)]
+pub struct Connection {
+    tx: mpsc::UnboundedSender<Event>,
+}
+
+impl Connection {
+    /// Report a message sent by `sender`.
+    pub fn message(&self, sender: &impl Display) {
+        let _ = self.tx.send(Event::Message {
+            sender: sender.to_string(),
+        });
+    }
+}
+
+impl Drop for Connection {
+    fn drop(&mut self) {
+        let _ = self.tx.send(Event::Disconnected);
+    }
+}
+
+/// Statistics at a point in time.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Stats {
+    /// Number of connected clients.
+    pub clients: usize,
+    /// Number of messages sent since the start.
+    pub messages: u64,
+    /// Number of messages sent in the last minute.
+    pub per_minute: usize,
+    /// Client which sent the most messages in the last minute, and how many.
+    pub busiest: Option<(String, usize)>,
+}
+
+impl Display for Stats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} client(s) connected, {} message(s) per minute, busiest sender: ",
+            self.clients, self.per_minute
+        )?;
+        match &self.busiest {
+            Some((sender, count)) => write!(f, "{sender} ({count})"),
+            None => write!(f, "nobody"),
+        }
+    }
+}
+
+/// Receive events on the given [`mpsc::UnboundedReceiver`], keeping count of clients and messages.
+/// On receiving a query including a reply callback ([`oneshot::Sender`]), send the current [`Stats`] on it.
+///
+/// # Termination
+/// In case there are no more senders, terminate the future.
+pub async fn collect(mut rx: mpsc::UnboundedReceiver<Event>) -> anyhow::Result<()> {
+    let mut clients = 0;
+    let mut messages = 0;
+    let mut recent: VecDeque<(Instant, String)> = VecDeque::new();
+    while let Some(event) = rx.recv().await {
+        let now = Instant::now();
+        while recent
+            .front()
+            .is_some_and(|(at, _)| now.duration_since(*at) >= WINDOW)
+        {
+            recent.pop_front();
+        }
+        match event {
+            Event::Connected => clients += 1,
+            Event::Disconnected => clients -= 1,
+            Event::Message { sender } => {
+                messages += 1;
+                recent.push_back((now, sender));
+            }
+            Event::Query { reply } => {
+                let stats = Stats {
+                    clients,
+                    messages,
+                    per_minute: recent.len(),
+                    busiest: busiest(recent.iter().map(|(_, sender)| sender.as_str())),
+                };
+                if reply.send(stats).is_err() {
+                    tracing::warn!("Failed to send statistics on callback");
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+/// The most frequent of `senders` and its count, the smallest one on ties.
+fn busiest<'a>(senders: impl Iterator<Item = &'a str>) -> Option<(String, usize)> {
+    let mut counts: HashMap<&str, usize> = HashMap::new();
+    for sender in senders {
+        *counts.entry(sender).or_default() += 1;
+    }
+    counts
+        .into_iter()
+        .max_by(|(a, a_count), (b, b_count)| a_count.cmp(b_count).then(b.cmp(a)))
+        .map(|(sender, count)| (sender.to_string(), count))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[tokio::test(start_paused = true)]
+    async fn counts_clients_and_messages() {
+        let (stats, rx) = channel();
+        tokio::spawn(collect(rx));
+
+        let alice = stats.connect();
+        let bob = stats.connect();
+        alice.message(&"alice");
+        bob.message(&"bob");
+        bob.message(&"bob");
+        drop(alice);
+
+        let expected = Stats {
+            clients: 1,
+            messages: 3,
+            per_minute: 3,
+            busiest: Some(("bob".to_string(), 2)),
+        };
+        assert_eq!(stats.query().await.unwrap(), expected);
+        assert_eq!(
+            expected.to_string(),
+            "1 client(s) connected, 3 message(s) per minute, busiest sender: bob (2)"
+        );
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn forgets_messages_older_than_a_minute() {
+        let (stats, rx) = channel();
+        tokio::spawn(collect(rx));
+
+        let alice = stats.connect();
+        alice.message(&"alice");
+        tokio::time::sleep(Duration::from_secs(30)).await;
+        alice.message(&"bob");
+        tokio::time::sleep(Duration::from_secs(30)).await;
+
+        let stats = stats.query().await.unwrap();
+        assert_eq!(stats.messages, 2);
+        assert_eq!(stats.per_minute, 1);
+        assert_eq!(stats.busiest, Some(("bob".to_string(), 1)));
+    }
+
+    #[test]
+    fn displays_without_messages() {
+        let stats = Stats {
+            clients: 0,
+            messages: 0,
+            per_minute: 0,
+            busiest: None,
+        };
+        assert_eq!(
+            stats.to_string(),
+            "0 client(s) connected, 0 message(s) per minute, busiest sender: nobody"
+        );
+    }
+}
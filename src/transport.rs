@@ -0,0 +1,247 @@
+use anyhow::Context;
+use std::{fmt, future::Future, io, net::SocketAddr, str::FromStr};
+use tokio::{
+    io::{AsyncRead, AsyncWrite, DuplexStream},
+    net::{TcpListener, TcpStream},
+    sync::mpsc,
+};
+
+/// Identity of a connected client.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum PeerId {
+    /// Remote address of a TCP connection.
+    Tcp(SocketAddr),
+    /// Number of a Unix socket connection, counting from `0` per listener.
+    Unix(u64),
+    /// Number of an in-memory connection, counting from `0` per listener.
+    Memory(u64),
+}
+
+impl fmt::Display for PeerId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Tcp(addr) => write!(f, "{addr}"),
+            Self::Unix(n) => write!(f, "unix:{n}"),
+            Self::Memory(n) => write!(f, "memory:{n}"),
+        }
+    }
+}
+
+impl FromStr for PeerId {
+    type Err = anyhow::Error;
+
+    /// Parse the [`Display`](fmt::Display) form: an address with port, `unix:<n>` or `memory:<n>`.
+    fn from_str(peer: &str) -> Result<Self, Self::Err> {
+        if let Some(n) = peer.strip_prefix("unix:") {
+            return Ok(Self::Unix(
+                n.parse()
+                    .with_context(|| format!("Invalid peer {peer:?}"))?,
+            ));
+        }
+        if let Some(n) = peer.strip_prefix("memory:") {
+            return Ok(Self::Memory(
+                n.parse()
+                    .with_context(|| format!("Invalid peer {peer:?}"))?,
+            ));
+        }
+        peer.parse().map(Self::Tcp).with_context(|| {
+            format!("Invalid peer {peer:?}, expected an address with port, unix:<n> or memory:<n>")
+        })
+    }
+}
+
+impl From<SocketAddr> for PeerId {
+    fn from(addr: SocketAddr) -> Self {
+        Self::Tcp(addr)
+    }
+}
+
+/// A source of client connections.
+pub trait Acceptor: Send + 'static {
+    /// Bidirectional stream to a client.
+    type Stream: AsyncRead + AsyncWrite + Send + Unpin + 'static;
+
+    /// Address clients use to reach this acceptor.
+    type Addr: fmt::Debug + Clone + Send + 'static;
+
+    /// Wait for the next client.
+    fn accept(&mut self) -> impl Future<Output = io::Result<(Self::Stream, PeerId)>> + Send;
+
+    /// The address this acceptor is reachable on.
+    ///
+    /// # Errors
+    /// Returns an error if the address can not be determined.
+    fn local_addr(&self) -> io::Result<Self::Addr>;
+}
+
+impl Acceptor for TcpListener {
+    type Stream = TcpStream;
+    type Addr = SocketAddr;
+
+    async fn accept(&mut self) -> io::Result<(Self::Stream, PeerId)> {
+        let (stream, addr) = TcpListener::accept(self).await?;
+        Ok((stream, PeerId::Tcp(addr)))
+    }
+
+    fn local_addr(&self) -> io::Result<Self::Addr> {
+        TcpListener::local_addr(self)
+    }
+}
+
+/// Accepts clients on a Unix domain socket.
+#[cfg(unix)]
+#[derive(Debug)]
+pub struct UnixAcceptor {
+    listener: tokio::net::UnixListener,
+    next: u64,
+}
+
+#[cfg(unix)]
+impl UnixAcceptor {
+    /// Bind a Unix domain socket at `path`.
+    ///
+    /// # Errors
+    /// Returns an error if binding fails, for example if `path` already exists.
+    pub fn bind(path: impl AsRef<std::path::Path>) -> io::Result<Self> {
+        Ok(Self {
+            listener: tokio::net::UnixListener::bind(path)?,
+            next: 0,
+        })
+    }
+}
+
+#[cfg(unix)]
+impl Acceptor for UnixAcceptor {
+    type Stream = tokio::net::UnixStream;
+    type Addr = tokio::net::unix::SocketAddr;
+
+    async fn accept(&mut self) -> io::Result<(Self::Stream, PeerId)> {
+        let (stream, _addr) = self.listener.accept().await?;
+        let peer = PeerId::Unix(self.next);
+        self.next += 1;
+        Ok((stream, peer))
+    }
+
+    fn local_addr(&self) -> io::Result<Self::Addr> {
+        self.listener.local_addr()
+    }
+}
+
+/// Create an in-memory listener.
+/// Clients connect via the returned [`MemoryAddr`], each connection being a [`tokio::io::duplex`] pipe
+/// with `buffer` bytes of capacity in each direction.
+pub fn memory(buffer: usize) -> (MemoryAddr, MemoryAcceptor) {
+    let (tx, rx) = mpsc::channel(16);
+    let addr = MemoryAddr { tx, buffer };
+    let acceptor = MemoryAcceptor {
+        addr: addr.clone(),
+        rx,
+        next: 0,
+    };
+    (addr, acceptor)
+}
+
+/// Address of a [`MemoryAcceptor`], used to connect to it.
+#[derive(Debug, Clone)]
+pub struct MemoryAddr {
+    tx: mpsc::Sender<DuplexStream>,
+    buffer: usize,
+}
+
+impl MemoryAddr {
+    /// Open a connection to the acceptor, returning the client end.
+    ///
+    /// # Errors
+    /// Returns [`io::ErrorKind::ConnectionRefused`] if the acceptor was dropped.
+    pub async fn connect(&self) -> io::Result<DuplexStream> {
+        let (client, server) = tokio::io::duplex(self.buffer);
+        self.tx.send(server).await.map_err(|_| {
+            io::Error::new(io::ErrorKind::ConnectionRefused, "Memory acceptor is gone")
+        })?;
+        Ok(client)
+    }
+}
+
+/// Accepts in-memory connections opened via [`MemoryAddr::connect`].
+#[derive(Debug)]
+pub struct MemoryAcceptor {
+    addr: MemoryAddr,
+    rx: mpsc::Receiver<DuplexStream>,
+    next: u64,
+}
+
+impl Acceptor for MemoryAcceptor {
+    type Stream = DuplexStream;
+    type Addr = MemoryAddr;
+
+    async fn accept(&mut self) -> io::Result<(Self::Stream, PeerId)> {
+        // Never `None`, as `self.addr` keeps the channel open.
+        let stream = self.rx.recv().await.ok_or_else(|| {
+            io::Error::new(io::ErrorKind::ConnectionAborted, "Memory listener closed")
+        })?;
+        let peer = PeerId::Memory(self.next);
+        self.next += 1;
+        Ok((stream, peer))
+    }
+
+    fn local_addr(&self) -> io::Result<Self::Addr> {
+        Ok(self.addr.clone())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    #[tokio::test]
+    async fn connects_in_memory() {
+        let (addr, mut acceptor) = memory(64);
+
+        let mut client = addr.connect().await.unwrap();
+        let (mut server, peer) = acceptor.accept().await.unwrap();
+        assert_eq!(peer, PeerId::Memory(0));
+        assert_eq!(peer.to_string(), "memory:0");
+
+        client.write_all(b"ping").await.unwrap();
+        let mut buffer = [0; 4];
+        server.read_exact(&mut buffer).await.unwrap();
+        assert_eq!(&buffer, b"ping");
+
+        let _second = addr.connect().await.unwrap();
+        assert_eq!(acceptor.accept().await.unwrap().1, PeerId::Memory(1));
+    }
+
+    #[test]
+    fn parses_displayed_peers() {
+        for peer in [
+            PeerId::Tcp("127.0.0.1:4000".parse().unwrap()),
+            PeerId::Tcp("[::1]:4000".parse().unwrap()),
+            PeerId::Unix(3),
+            PeerId::Memory(0),
+        ] {
+            assert_eq!(peer.to_string().parse::<PeerId>().unwrap(), peer);
+        }
+        assert!("127.0.0.1".parse::<PeerId>().is_err());
+        assert!("unix:x".parse::<PeerId>().is_err());
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn connects_via_unix_socket() {
+        let path = std::env::temp_dir().join(format!("achat-{}.sock", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let mut acceptor = UnixAcceptor::bind(&path).unwrap();
+
+        let mut client = tokio::net::UnixStream::connect(&path).await.unwrap();
+        let (mut server, peer) = acceptor.accept().await.unwrap();
+        assert_eq!(peer.to_string(), "unix:0");
+
+        server.write_all(b"pong").await.unwrap();
+        let mut buffer = [0; 4];
+        client.read_exact(&mut buffer).await.unwrap();
+        assert_eq!(&buffer, b"pong");
+
+        std::fs::remove_file(&path).unwrap();
+    }
+}
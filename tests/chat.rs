@@ -0,0 +1,53 @@
+mod support;
+
+use achat::serve::Kind;
+use std::time::Duration;
+
+#[tokio::test]
+async fn broadcasts_to_all_other_clients() {
+    let server = support::start(Kind::Chat).await;
+    let mut clients = support::connect_chat_clients(&server, 3).await;
+
+    clients[0].send("hello").await;
+    let expected = format!("{}: hello", clients[0].addr());
+    clients[1].expect(&expected).await;
+    clients[2].expect(&expected).await;
+    clients[0].expect_silence(Duration::from_millis(100)).await;
+
+    drop(clients);
+    server.stop().await;
+}
+
+#[tokio::test]
+async fn forwards_each_line_once() {
+    let server = support::start(Kind::Chat).await;
+    let mut clients = support::connect_chat_clients(&server, 2).await;
+
+    clients[0].send("one").await;
+    clients[0].send("two").await;
+    let addr = clients[0].addr();
+    clients[1].expect(&format!("{addr}: one")).await;
+    clients[1].expect(&format!("{addr}: two")).await;
+
+    drop(clients);
+    server.stop().await;
+}
+
+#[tokio::test]
+async fn quit_disconnects_only_the_sender() {
+    let server = support::start(Kind::Chat).await;
+    let mut clients = support::connect_chat_clients(&server, 2).await;
+
+    clients[0].send("quit").await;
+    clients[0].expect_closed().await;
+
+    clients[1].send("anyone?").await;
+    clients[1].expect_silence(Duration::from_millis(100)).await;
+
+    let mut late = server.connect().await;
+    late.send("hi").await;
+    clients[1].expect(&format!("{}: hi", late.addr())).await;
+
+    drop((clients, late));
+    server.stop().await;
+}
@@ -0,0 +1,226 @@
+mod support;
+
+use achat::{
+    serve::{self, Kind},
+    Config,
+};
+use support::TestClient;
+use tokio::sync::watch;
+
+/// Receive the next line which is not an announcement.
+async fn recv_skipping_announcements(client: &mut TestClient) -> String {
+    loop {
+        let line = client.recv().await;
+        if !line.starts_with("Announcement: ") {
+            break line;
+        }
+    }
+}
+
+#[tokio::test]
+async fn announces_uptime_and_broadcasts() {
+    let mut config = support::config();
+    config.chat_with_announce.interval_secs = 1;
+    let server = support::start_with(Kind::Announce, &config).await;
+
+    let mut alice = server.connect().await;
+    alice.expect("Topic: Chat topic").await;
+    let announcement = alice.recv().await;
+    assert!(
+        announcement.starts_with("Announcement: Up for "),
+        "{announcement}"
+    );
+
+    let mut bob = server.connect().await;
+    bob.expect("Topic: Chat topic").await;
+    bob.send("hello").await;
+    assert_eq!(
+        recv_skipping_announcements(&mut alice).await,
+        format!("{}: hello", bob.addr())
+    );
+
+    drop((alice, bob));
+    server.stop().await;
+}
+
+#[tokio::test]
+async fn forwards_each_line_once() {
+    let server = support::start(Kind::Announce).await;
+    let mut alice = server.connect().await;
+    let mut bob = server.connect().await;
+    alice.expect("Topic: Chat topic").await;
+    bob.expect("Topic: Chat topic").await;
+
+    bob.send("one").await;
+    bob.send("two").await;
+    let addr = bob.addr();
+    assert_eq!(
+        recv_skipping_announcements(&mut alice).await,
+        format!("{addr}: one")
+    );
+    assert_eq!(
+        recv_skipping_announcements(&mut alice).await,
+        format!("{addr}: two")
+    );
+
+    drop((alice, bob));
+    server.stop().await;
+}
+
+#[tokio::test]
+async fn clients_change_the_topic() {
+    let server = support::start(Kind::Announce).await;
+
+    let mut alice = server.connect().await;
+    let mut bob = server.connect().await;
+    alice.expect("Topic: Chat topic").await;
+    bob.expect("Topic: Chat topic").await;
+
+    alice.send("/topic Rust").await;
+    assert_eq!(recv_skipping_announcements(&mut alice).await, "Topic: Rust");
+    assert_eq!(recv_skipping_announcements(&mut bob).await, "Topic: Rust");
+
+    let mut carol = server.connect().await;
+    carol.expect("Topic: Rust").await;
+
+    drop((alice, bob, carol));
+    server.stop().await;
+}
+
+#[tokio::test]
+async fn only_setters_change_the_topic() {
+    let mut config = support::config();
+    config.chat_with_announce.topic_setters = Some(vec!["10.0.0.1".to_string()]);
+    let server = support::start_with(Kind::Announce, &config).await;
+
+    let mut alice = server.connect().await;
+    alice.expect("Topic: Chat topic").await;
+    alice.send("/topic Rust").await;
+    assert_eq!(
+        recv_skipping_announcements(&mut alice).await,
+        "Not allowed to change the topic"
+    );
+
+    drop(alice);
+    server.stop().await;
+}
+
+#[tokio::test]
+async fn clients_query_stats() {
+    let server = support::start(Kind::Announce).await;
+
+    let mut alice = server.connect().await;
+    let mut bob = server.connect().await;
+    alice.expect("Topic: Chat topic").await;
+    bob.expect("Topic: Chat topic").await;
+
+    bob.send("hello").await;
+    assert_eq!(
+        recv_skipping_announcements(&mut alice).await,
+        format!("{}: hello", bob.addr())
+    );
+    alice.send("/stats").await;
+    assert_eq!(
+        recv_skipping_announcements(&mut alice).await,
+        format!(
+            "Stats: 2 client(s) connected, 1 message(s) per minute, busiest sender: {} (1)",
+            bob.addr()
+        )
+    );
+
+    drop((alice, bob));
+    server.stop().await;
+}
+
+#[tokio::test]
+async fn greets_with_motd_and_announces_changes() {
+    let directory = std::env::temp_dir().join(format!("achat-test-motd-{}", std::process::id()));
+    std::fs::create_dir_all(&directory).unwrap();
+    let path = directory.join("motd.txt");
+    std::fs::write(&path, "Welcome!\nBe nice.").unwrap();
+
+    let mut config = support::config();
+    config.server.motd = Some(path.clone());
+    let server = support::start_with(Kind::Announce, &config).await;
+
+    let mut alice = server.connect().await;
+    alice.expect("MOTD: Welcome!").await;
+    alice.expect("MOTD: Be nice.").await;
+    alice.expect("Topic: Chat topic").await;
+
+    std::fs::write(&path, "Maintenance at noon").unwrap();
+    // The file may be noticed empty halfway through writing, which announces nothing.
+    assert_eq!(
+        recv_skipping_uptime(&mut alice).await,
+        "Announcement: Maintenance at noon"
+    );
+
+    drop(alice);
+    server.stop().await;
+    std::fs::remove_dir_all(&directory).unwrap();
+}
+
+#[tokio::test]
+async fn stops_announcing_on_shutdown() {
+    let config = support::config();
+    let (schedule_tx, schedule) =
+        watch::channel(config.chat_with_announce.announcements().unwrap());
+    let handle = serve::chat_with_announce_in(&config, schedule)
+        .await
+        .unwrap();
+
+    tokio::time::timeout(support::TIMEOUT, handle.stop())
+        .await
+        .unwrap()
+        .unwrap();
+    // Joining waited for the scheduler, which dropped its receiver.
+    assert_eq!(schedule_tx.receiver_count(), 0);
+}
+
+#[cfg(unix)]
+#[tokio::test]
+async fn reloads_announcements_on_hangup() {
+    let directory = std::env::temp_dir().join(format!("achat-test-reload-{}", std::process::id()));
+    std::fs::create_dir_all(&directory).unwrap();
+    let path = directory.join("achat.toml");
+    let config = |text: &str| {
+        format!(
+            "[server]\naddress = \"127.0.0.1:0\"\n\n\
+             [[chat_with_announce.announcements]]\ntext = \"{text}\"\ninterval_secs = 1\n"
+        )
+    };
+    std::fs::write(&path, config("before")).unwrap();
+
+    let server = support::start_with(Kind::Announce, &Config::from_file(&path).unwrap()).await;
+    let mut alice = server.connect().await;
+    alice.expect("Topic: Chat topic").await;
+    alice.expect("Announcement: before").await;
+
+    std::fs::write(&path, config("after")).unwrap();
+    let status = std::process::Command::new("kill")
+        .args(["-HUP", &std::process::id().to_string()])
+        .status()
+        .unwrap();
+    assert!(status.success());
+    loop {
+        let line = alice.recv().await;
+        if line != "Announcement: before" {
+            assert_eq!(line, "Announcement: after");
+            break;
+        }
+    }
+
+    drop(alice);
+    server.stop().await;
+    std::fs::remove_dir_all(&directory).unwrap();
+}
+
+/// Receive the next line which is not the uptime announcement.
+async fn recv_skipping_uptime(client: &mut TestClient) -> String {
+    loop {
+        let line = client.recv().await;
+        if !line.starts_with("Announcement: Up for ") {
+            break line;
+        }
+    }
+}
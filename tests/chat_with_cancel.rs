@@ -0,0 +1,151 @@
+mod support;
+
+use achat::{chat_with_cancel::Registry, serve, serve::Kind, server::Shutdown};
+use std::time::Duration;
+
+/// Configuration allowing shutdown with `call it a day s3cret`.
+fn config() -> achat::Config {
+    let mut config = support::config();
+    config.chat_with_cancel.secret = Some("s3cret".to_string());
+    config
+}
+
+#[tokio::test]
+async fn call_it_a_day_shuts_down_everyone() {
+    let mut config = config();
+    config.server.grace_period_secs = 1;
+    let server = support::start_with(Kind::Cancel, &config).await;
+    let mut clients = support::connect_chat_clients(&server, 3).await;
+
+    clients[0].send("call it a day s3cret").await;
+    let announcement = format!("Shutdown requested by {}", clients[0].addr());
+    let notice = "Server shutting down in 1 second";
+
+    clients[0].expect(notice).await;
+    for client in &mut clients[1..] {
+        // The announcement is sent before shutting down, but may be forwarded before or after the notice.
+        let mut lines = [client.recv().await, client.recv().await];
+        lines.sort_unstable();
+        assert_eq!(lines, [notice, announcement.as_str()]);
+    }
+
+    // Until the grace period ends, clients are still served.
+    clients[1].send("last words").await;
+    let expected = format!("{}: last words", clients[1].addr());
+    clients[0].expect(&expected).await;
+    clients[2].expect(&expected).await;
+
+    for client in &mut clients {
+        client.expect_closed().await;
+    }
+    server.join().await;
+}
+
+#[tokio::test]
+async fn forwards_each_line_once() {
+    let server = support::start(Kind::Cancel).await;
+    let mut clients = support::connect_chat_clients(&server, 2).await;
+
+    clients[1].send("one").await;
+    clients[1].send("two").await;
+    let addr = clients[1].addr();
+    clients[0].expect(&format!("{addr}: one")).await;
+    clients[0].expect(&format!("{addr}: two")).await;
+
+    drop(clients);
+    server.stop().await;
+}
+
+#[tokio::test]
+async fn rejects_call_it_a_day_without_secret() {
+    let server = support::start_with(Kind::Cancel, &config()).await;
+    let mut clients = support::connect_chat_clients(&server, 2).await;
+
+    clients[0].send("call it a day guess").await;
+    clients[0].expect("Shutdown not permitted").await;
+    clients[1].expect_silence(Duration::from_millis(100)).await;
+
+    clients[0].send("still up").await;
+    let expected = format!("{}: still up", clients[0].addr());
+    clients[1].expect(&expected).await;
+
+    drop(clients);
+    server.stop().await;
+}
+
+#[tokio::test]
+async fn broadcasts_until_cancelled() {
+    let server = support::start(Kind::Cancel).await;
+    let mut clients = support::connect_chat_clients(&server, 2).await;
+
+    clients[1].send("hello").await;
+    let expected = format!("{}: hello", clients[1].addr());
+    clients[0].expect(&expected).await;
+
+    drop(clients);
+    server.stop().await;
+}
+
+#[tokio::test]
+async fn cancels_a_single_client() {
+    let registry = Registry::default();
+    let handle = serve::chat_with_cancel_in(&config(), registry.clone())
+        .await
+        .unwrap();
+    let mut kicked = support::TestClient::connect(handle.local_addr()).await;
+    let mut other = support::TestClient::connect(handle.local_addr()).await;
+    // Once the message arrives, both clients are registered.
+    other.send("sync").await;
+    kicked.expect(&format!("{}: sync", other.addr())).await;
+
+    assert!(registry.cancel(&kicked.addr().into()));
+    kicked.expect_closed().await;
+
+    other.send("still here").await;
+    other
+        .expect_silence(std::time::Duration::from_millis(100))
+        .await;
+    assert_eq!(registry.peers(), vec![other.addr().into()]);
+
+    registry.shutdown();
+    other.expect("Server shutting down in 5 seconds").await;
+    // Leaving before the grace period ends.
+    drop(other);
+    tokio::time::timeout(support::TIMEOUT, handle.join())
+        .await
+        .unwrap()
+        .unwrap();
+}
+
+#[tokio::test]
+async fn disconnects_lingering_clients_when_grace_period_ends() {
+    let mut config = config();
+    config.server.grace_period_secs = 1;
+    let registry = Registry::default();
+    let handle = serve::chat_with_cancel_in(&config, registry.clone())
+        .await
+        .unwrap();
+    let mut lingering = support::TestClient::connect(handle.local_addr()).await;
+    let mut other = support::TestClient::connect(handle.local_addr()).await;
+    // Once the message arrives, both clients are served.
+    other.send("sync").await;
+    lingering.expect(&format!("{}: sync", other.addr())).await;
+
+    registry.shutdown();
+    for client in [&mut lingering, &mut other] {
+        client.expect("Server shutting down in 1 second").await;
+        client.expect_closed().await;
+    }
+    let report = tokio::time::timeout(support::TIMEOUT, handle.join_report())
+        .await
+        .unwrap()
+        .unwrap();
+    // The clients are disconnected by their handlers, not aborted by the server.
+    assert_eq!(
+        report,
+        Shutdown {
+            finished: 2,
+            ..Shutdown::default()
+        }
+    );
+}
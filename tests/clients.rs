@@ -0,0 +1,176 @@
+//! Runs the client binaries against an in-process echo server,
+//! and the server binaries against in-process clients.
+
+mod support;
+
+use achat::serve::Kind;
+use serde_json::json;
+use std::{net::SocketAddr, process::Stdio};
+use support::TestClient;
+use tokio::{
+    io::{AsyncBufReadExt, AsyncWriteExt, BufReader},
+    process::{Child, Command},
+};
+
+/// Start `binary` with `args`, send a line on its `stdin`, and expect it echoed on its `stdout`.
+async fn echoes_through(binary: &str, args: &[&str]) {
+    let server = support::start(Kind::Echo).await;
+
+    let mut child = Command::new(binary)
+        .args(["--address", &server.addr().to_string()])
+        .args(args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .kill_on_drop(true)
+        .spawn()
+        .unwrap();
+
+    let mut stdin = child.stdin.take().unwrap();
+    let mut stdout = BufReader::new(child.stdout.take().unwrap());
+
+    stdin.write_all(b"hello\n").await.unwrap();
+    let mut line = String::new();
+    tokio::time::timeout(support::TIMEOUT, stdout.read_line(&mut line))
+        .await
+        .expect("Timed out waiting for client output")
+        .unwrap();
+    assert_eq!(line, "hello\n");
+
+    child.kill().await.unwrap();
+    server.stop().await;
+}
+
+#[tokio::test]
+async fn client() {
+    echoes_through(env!("CARGO_BIN_EXE_client"), &[]).await;
+}
+
+#[tokio::test]
+async fn client_2() {
+    echoes_through(env!("CARGO_BIN_EXE_client_2"), &[]).await;
+}
+
+#[tokio::test]
+async fn client_3() {
+    echoes_through(env!("CARGO_BIN_EXE_client_3"), &[]).await;
+}
+
+#[tokio::test]
+async fn achat_connect() {
+    echoes_through(env!("CARGO_BIN_EXE_achat"), &["connect"]).await;
+}
+
+/// Start the server `binary` on an ephemeral port, and return it once it is listening, together with its address.
+async fn listening(binary: &str) -> (Child, SocketAddr) {
+    let mut child = Command::new(binary)
+        .args([
+            "--address",
+            "127.0.0.1:0",
+            "--log",
+            "info",
+            "--log-format",
+            "json",
+        ])
+        .stdout(Stdio::piped())
+        .kill_on_drop(true)
+        .spawn()
+        .unwrap();
+
+    let mut logs = BufReader::new(child.stdout.take().unwrap()).lines();
+    let addr = tokio::time::timeout(support::TIMEOUT, async {
+        loop {
+            let line = logs
+                .next_line()
+                .await
+                .unwrap()
+                .expect("Server exited before listening");
+            let event: serde_json::Value = serde_json::from_str(&line).unwrap();
+            if event["fields"]["message"] == "Listening" {
+                break event["fields"]["address"]
+                    .as_str()
+                    .unwrap()
+                    .parse()
+                    .unwrap();
+            }
+        }
+    })
+    .await
+    .expect("Timed out waiting for the server to listen");
+    // Keep draining the logs, so the server never blocks writing them.
+    tokio::spawn(async move { while let Ok(Some(_)) = logs.next_line().await {} });
+    (child, addr)
+}
+
+/// Receive the next line which is not an announcement.
+async fn recv_skipping_announcements(client: &mut TestClient) -> String {
+    loop {
+        let line = client.recv().await;
+        if !line.starts_with("Announcement: ") {
+            break line;
+        }
+    }
+}
+
+/// Start the chat server `binary`, and expect a line sent by one client to reach another.
+/// Each client is first sent the `greeting` lines.
+async fn chats_through(binary: &str, greeting: &[&str]) {
+    let (mut child, addr) = listening(binary).await;
+
+    let mut alice = TestClient::connect(addr).await;
+    let mut bob = TestClient::connect(addr).await;
+    for client in [&mut alice, &mut bob] {
+        for line in greeting {
+            assert_eq!(recv_skipping_announcements(client).await, *line);
+        }
+    }
+    bob.send("hello").await;
+    assert_eq!(
+        recv_skipping_announcements(&mut alice).await,
+        format!("{}: hello", bob.addr())
+    );
+
+    child.kill().await.unwrap();
+}
+
+#[tokio::test]
+async fn echo() {
+    let (mut child, addr) = listening(env!("CARGO_BIN_EXE_echo")).await;
+
+    let mut client = TestClient::connect(addr).await;
+    client.send("hello").await;
+    client.expect("hello").await;
+
+    child.kill().await.unwrap();
+}
+
+#[tokio::test]
+async fn chat() {
+    chats_through(env!("CARGO_BIN_EXE_chat"), &[]).await;
+}
+
+#[tokio::test]
+async fn chat_with_announce() {
+    chats_through(
+        env!("CARGO_BIN_EXE_chat_with_announce"),
+        &["Topic: Chat topic"],
+    )
+    .await;
+}
+
+#[tokio::test]
+async fn chat_with_cancel() {
+    chats_through(env!("CARGO_BIN_EXE_chat_with_cancel"), &[]).await;
+}
+
+#[tokio::test]
+async fn collector() {
+    let (mut child, addr) = listening(env!("CARGO_BIN_EXE_collector")).await;
+
+    let mut client = TestClient::connect(addr).await;
+    client.send("hello").await;
+    client.send("report").await;
+    let report: serde_json::Value = serde_json::from_str(&client.recv_until("}").await).unwrap();
+    assert_eq!(report, json!({ client.addr().to_string(): ["hello\n"] }));
+
+    child.kill().await.unwrap();
+}
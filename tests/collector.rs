@@ -0,0 +1,185 @@
+mod support;
+
+use achat::serve::Kind;
+use serde_json::json;
+use std::time::Duration;
+
+#[tokio::test]
+async fn reports_messages_of_all_clients() {
+    let server = support::start(Kind::Collector).await;
+    let mut alice = server.connect().await;
+    let mut bob = server.connect().await;
+
+    alice.send("one").await;
+    bob.send("two").await;
+    alice.send("three").await;
+
+    // Reports are answered in order with the messages sent before, per client.
+    bob.send("report").await;
+    let _ = bob.recv_until("}").await;
+    alice.send("report").await;
+    let report: serde_json::Value = serde_json::from_str(&alice.recv_until("}").await).unwrap();
+
+    assert_eq!(
+        report,
+        json!({
+            alice.addr().to_string(): ["one\n", "three\n"],
+            bob.addr().to_string(): ["two\n"],
+        })
+    );
+
+    drop((alice, bob));
+    server.stop().await;
+}
+
+#[tokio::test]
+async fn reports_matching_messages() {
+    let server = support::start(Kind::Collector).await;
+    let mut alice = server.connect().await;
+    let mut bob = server.connect().await;
+
+    alice.send("hi bob").await;
+    bob.send("hi alice").await;
+    bob.send("bye").await;
+    // Wait until bob's messages are collected.
+    bob.send("report").await;
+    let _ = bob.recv_until("}").await;
+
+    alice
+        .send(&format!("report sender={} grep=hi", bob.addr()))
+        .await;
+    let report: serde_json::Value = serde_json::from_str(&alice.recv_until("}").await).unwrap();
+    assert_eq!(report, json!({ bob.addr().to_string(): ["hi alice\n"] }));
+
+    alice.send("report last=1").await;
+    let report: serde_json::Value = serde_json::from_str(&alice.recv_until("}").await).unwrap();
+    assert_eq!(report, json!({ bob.addr().to_string(): ["bye\n"] }));
+
+    alice.send("report last=few").await;
+    alice
+        .expect("Invalid query: invalid count \"few\": invalid digit found in string")
+        .await;
+
+    drop((alice, bob));
+    server.stop().await;
+}
+
+#[tokio::test]
+async fn restores_snapshot_after_restart() {
+    let directory =
+        std::env::temp_dir().join(format!("achat-test-snapshot-{}", std::process::id()));
+    std::fs::create_dir_all(&directory).unwrap();
+    let mut config = support::config();
+    config.collector.snapshot = Some(directory.join("collector.json"));
+
+    let server = support::start_with(Kind::Collector, &config).await;
+    let mut alice = server.connect().await;
+    alice.send("before snapshot").await;
+    alice.send("snapshot").await;
+    alice.expect("Snapshot of 1 message(s) saved").await;
+    alice.send("after snapshot").await;
+    alice.send("report").await;
+    let before: serde_json::Value = serde_json::from_str(&alice.recv_until("}").await).unwrap();
+    drop(alice);
+    // Stopping writes the final snapshot.
+    server.stop().await;
+
+    let server = support::start_with(Kind::Collector, &config).await;
+    let mut bob = server.connect().await;
+    bob.send("report").await;
+    let after: serde_json::Value = serde_json::from_str(&bob.recv_until("}").await).unwrap();
+    assert_eq!(after, before);
+    drop(bob);
+    server.stop().await;
+
+    std::fs::remove_dir_all(&directory).unwrap();
+}
+
+#[tokio::test]
+async fn rejects_snapshot_without_file() {
+    let server = support::start(Kind::Collector).await;
+    let mut alice = server.connect().await;
+    alice.send("snapshot").await;
+    alice.expect("Snapshots are not configured").await;
+    drop(alice);
+    server.stop().await;
+}
+
+#[tokio::test]
+async fn streams_followed_messages_until_unfollowed() {
+    let server = support::start(Kind::Collector).await;
+    let mut alice = server.connect().await;
+    let mut bob = server.connect().await;
+    let mut carol = server.connect().await;
+
+    alice.send(&format!("follow {}", bob.addr())).await;
+    alice.expect(&format!("Following {}", bob.addr())).await;
+
+    carol.send("not followed").await;
+    bob.send("followed").await;
+    let line = alice.recv().await;
+    assert!(
+        line.ends_with(&format!(" {}: followed", bob.addr())),
+        "{line}"
+    );
+
+    alice.send("unfollow").await;
+    alice.expect("Unfollowed").await;
+    bob.send("no longer followed").await;
+    // Once bob's message is collected, it would have been streamed already.
+    bob.send("report").await;
+    let _ = bob.recv_until("}").await;
+    alice.expect_silence(Duration::from_millis(100)).await;
+
+    drop((alice, bob, carol));
+    server.stop().await;
+}
+
+#[tokio::test]
+async fn keys_messages_by_claimed_name() {
+    let server = support::start(Kind::Collector).await;
+    let mut first = server.connect().await;
+    let mut second = server.connect().await;
+
+    first.send("hello sensor-1").await;
+    first.expect("Hello, sensor-1").await;
+    second.send("hello sensor 2").await;
+    second
+        .expect("Invalid name: unexpected ' ', use letters, digits, '-', '_' or '.'")
+        .await;
+    second.send("hello sensor-1").await;
+    second.expect("Name sensor-1 is taken").await;
+    second.send("hello sensor-2").await;
+    second.expect("Hello, sensor-2").await;
+    // Only a handshake, once messages are sent.
+    second.send("hello sensor-3").await;
+    second.send("report").await;
+    let _ = second.recv_until("}").await;
+    first.send("first reading").await;
+    first.close().await;
+    first.expect_closed().await;
+
+    // Reconnecting under the same name continues its messages, once the old connection is gone.
+    let mut reconnected = server.connect().await;
+    loop {
+        reconnected.send("hello sensor-1").await;
+        if reconnected.recv().await == "Hello, sensor-1" {
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(10)).await;
+    }
+    reconnected.send("second reading").await;
+    reconnected.send("report").await;
+    let report: serde_json::Value =
+        serde_json::from_str(&reconnected.recv_until("}").await).unwrap();
+    assert_eq!(
+        report,
+        json!({
+            "sensor-1": ["first reading\n", "second reading\n"],
+            "sensor-2": ["hello sensor-3\n"],
+        })
+    );
+
+    drop((second, reconnected));
+    server.stop().await;
+}
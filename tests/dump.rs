@@ -0,0 +1,21 @@
+mod support;
+
+use achat::serve::Kind;
+
+#[tokio::test]
+async fn accepts_clients_and_stays_silent() {
+    let server = support::start(Kind::Dump).await;
+    let mut alice = server.connect().await;
+    let mut bob = server.connect().await;
+
+    alice.send("hello").await;
+    alice.close().await;
+    alice.expect_closed().await;
+
+    bob.send_raw(&[0xff, 0xfe]).await;
+    bob.close().await;
+    bob.expect_closed().await;
+
+    drop((alice, bob));
+    server.stop().await;
+}
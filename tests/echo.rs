@@ -0,0 +1,23 @@
+mod support;
+
+use achat::serve::Kind;
+
+#[tokio::test]
+async fn echoes_lines_to_each_client() {
+    let server = support::start(Kind::Echo).await;
+    let mut alice = server.connect().await;
+    let mut bob = server.connect().await;
+
+    alice.send("hello").await;
+    bob.send("hi").await;
+    alice.expect("hello").await;
+    bob.expect("hi").await;
+
+    alice.close().await;
+    alice.expect_closed().await;
+    bob.send("still there").await;
+    bob.expect("still there").await;
+
+    drop((alice, bob));
+    server.stop().await;
+}
@@ -0,0 +1,66 @@
+//! Runs the load generator against an in-process chat server.
+
+mod support;
+
+use achat::serve::Kind;
+use tokio::process::Command;
+
+#[tokio::test]
+async fn reports_broadcast_latency() {
+    let server = support::start(Kind::Chat).await;
+
+    let output = tokio::time::timeout(
+        support::TIMEOUT,
+        Command::new(env!("CARGO_BIN_EXE_loadgen"))
+            .args(["--address", &server.addr().to_string()])
+            .args(["--clients", "3", "--rate", "20", "--duration", "0.5"])
+            .args(["--drain", "0.5", "--json"])
+            .output(),
+    )
+    .await
+    .expect("Timed out waiting for loadgen")
+    .unwrap();
+    assert!(output.status.success(), "{output:?}");
+
+    let report: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(report["clients"], 3);
+    let sent = report["sent"].as_u64().unwrap();
+    assert!(sent > 0);
+    // Each message reaches at most the two other clients.
+    let received = report["received"].as_u64().unwrap();
+    assert!(received > 0 && received <= 2 * sent);
+    assert!(report["latency_ms"]["p99"].as_f64().is_some());
+
+    server.stop().await;
+}
+
+#[tokio::test]
+async fn rejects_invalid_arguments() {
+    for (argument, error) in [
+        ("--rate=1e300", "Rate 1e300 is too high"),
+        ("--rate=inf", "Rate must be a finite number"),
+        ("--duration=-1", "Invalid duration -1.0"),
+        ("--drain=NaN", "Invalid drain NaN"),
+        (
+            "--duration=1e19",
+            "Duration 10000000000000000000s is too long",
+        ),
+        ("--drain=1e19", "Drain 10000000000000000000s is too long"),
+    ] {
+        let output = tokio::time::timeout(
+            support::TIMEOUT,
+            Command::new(env!("CARGO_BIN_EXE_loadgen"))
+                // Nothing listens there, arguments are checked before connecting.
+                .args(["--address", "127.0.0.1:1"])
+                .arg(argument)
+                .output(),
+        )
+        .await
+        .expect("Timed out waiting for loadgen")
+        .unwrap();
+        assert!(!output.status.success(), "{argument}");
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        assert!(stderr.contains(error), "{argument}: {stderr}");
+        assert!(!stderr.contains("panicked"), "{argument}: {stderr}");
+    }
+}
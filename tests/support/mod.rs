@@ -0,0 +1,192 @@
+//! Helpers for running achat servers in-process and talking to them with scripted clients.
+
+#![allow(dead_code)]
+
+use achat::{
+    serve::{self, Kind},
+    server::ServerHandle,
+    Config,
+};
+use std::{net::SocketAddr, time::Duration};
+use tokio::{
+    io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader},
+    net::{
+        tcp::{OwnedReadHalf, OwnedWriteHalf},
+        TcpStream,
+    },
+};
+
+/// How long to wait for anything before failing the test.
+pub const TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Configuration listening on an ephemeral port of `127.0.0.1`.
+pub fn config() -> Config {
+    let mut config = Config::default();
+    config.server.address = "127.0.0.1:0".parse().unwrap();
+    config
+}
+
+/// Start the server of the given `kind` on an ephemeral port.
+pub async fn start(kind: Kind) -> TestServer {
+    start_with(kind, &config()).await
+}
+
+/// Start the server of the given `kind` with a custom `config`.
+pub async fn start_with(kind: Kind, config: &Config) -> TestServer {
+    let handle = serve::start(kind, config)
+        .await
+        .expect("Failed to start server");
+    TestServer { handle }
+}
+
+/// An achat server running in this process.
+pub struct TestServer {
+    handle: ServerHandle,
+}
+
+impl TestServer {
+    /// The address the server is bound to.
+    pub fn addr(&self) -> SocketAddr {
+        self.handle.local_addr()
+    }
+
+    /// Connect a new client.
+    pub async fn connect(&self) -> TestClient {
+        TestClient::connect(self.addr()).await
+    }
+
+    /// Wait for the server to shut down on its own.
+    pub async fn join(self) {
+        tokio::time::timeout(TIMEOUT, self.handle.join())
+            .await
+            .expect("Server did not shut down in time")
+            .unwrap();
+    }
+
+    /// Shut down the server and wait for it.
+    /// Drop the clients first, servers may wait for connected clients for a grace period.
+    pub async fn stop(self) {
+        tokio::time::timeout(TIMEOUT, self.handle.stop())
+            .await
+            .expect("Server did not stop in time")
+            .unwrap();
+    }
+}
+
+/// A client sending and expecting lines.
+pub struct TestClient {
+    addr: SocketAddr,
+    reader: BufReader<OwnedReadHalf>,
+    writer: OwnedWriteHalf,
+}
+
+impl TestClient {
+    /// Connect to the server at `addr`.
+    pub async fn connect(addr: SocketAddr) -> Self {
+        let stream = tokio::time::timeout(TIMEOUT, TcpStream::connect(addr))
+            .await
+            .expect("Timed out connecting")
+            .expect("Failed to connect");
+        let local = stream.local_addr().unwrap();
+        let (reader, writer) = stream.into_split();
+        Self {
+            addr: local,
+            reader: BufReader::new(reader),
+            writer,
+        }
+    }
+
+    /// The address of this client, as seen by the server.
+    pub fn addr(&self) -> SocketAddr {
+        self.addr
+    }
+
+    /// Send `line`, terminated by a newline.
+    pub async fn send(&mut self, line: &str) {
+        self.send_raw(format!("{line}\n").as_bytes()).await;
+    }
+
+    /// Send `bytes` as they are.
+    pub async fn send_raw(&mut self, bytes: &[u8]) {
+        tokio::time::timeout(TIMEOUT, self.writer.write_all(bytes))
+            .await
+            .expect("Timed out sending")
+            .expect("Failed to send");
+    }
+
+    /// Close the sending half of the connection.
+    pub async fn close(&mut self) {
+        self.writer.shutdown().await.expect("Failed to close");
+    }
+
+    /// Receive the next line, without its newline.
+    pub async fn recv(&mut self) -> String {
+        let mut line = String::new();
+        let n = tokio::time::timeout(TIMEOUT, self.reader.read_line(&mut line))
+            .await
+            .expect("Timed out waiting for a line")
+            .expect("Failed to receive");
+        assert!(n > 0, "Connection closed while waiting for a line");
+        line.truncate(line.trim_end_matches(['\r', '\n']).len());
+        line
+    }
+
+    /// Receive the next line and check it equals `expected`.
+    pub async fn expect(&mut self, expected: &str) {
+        assert_eq!(self.recv().await, expected);
+    }
+
+    /// Receive lines up to and including the first one equal to `last`, returning all of them joined by newlines.
+    pub async fn recv_until(&mut self, last: &str) -> String {
+        let mut lines = Vec::new();
+        loop {
+            let line = self.recv().await;
+            let done = line == last;
+            lines.push(line);
+            if done {
+                break lines.join("\n");
+            }
+        }
+    }
+
+    /// Check that nothing arrives for `duration`.
+    pub async fn expect_silence(&mut self, duration: Duration) {
+        let mut line = String::new();
+        if let Ok(result) = tokio::time::timeout(duration, self.reader.read_line(&mut line)).await {
+            panic!("Expected silence, got {result:?}: {line:?}");
+        }
+    }
+
+    /// Receive everything until the server closes the connection.
+    pub async fn recv_to_end(&mut self) -> String {
+        let mut rest = Vec::new();
+        tokio::time::timeout(TIMEOUT, self.reader.read_to_end(&mut rest))
+            .await
+            .expect("Timed out waiting for the connection to close")
+            .expect("Failed to receive");
+        String::from_utf8_lossy(&rest).into_owned()
+    }
+
+    /// Check that the server closes the connection without sending anything else.
+    pub async fn expect_closed(&mut self) {
+        let rest = self.recv_to_end().await;
+        assert!(rest.is_empty(), "Expected close, got {rest:?}");
+    }
+}
+
+/// Connect `n` clients to a chat server, making sure the server accepted all of them.
+///
+/// The last client announces itself with `"sync"`, which every other client receives.
+pub async fn connect_chat_clients(server: &TestServer, n: usize) -> Vec<TestClient> {
+    let mut clients = Vec::new();
+    for _ in 0..n {
+        clients.push(server.connect().await);
+    }
+    let last = clients.last_mut().unwrap();
+    last.send("sync").await;
+    let expected = format!("{}: sync", last.addr());
+    for client in &mut clients[..n - 1] {
+        client.expect(&expected).await;
+    }
+    clients
+}